@@ -7,7 +7,11 @@
 
 extern crate curl;
 extern crate docopt;
+extern crate libc;
+extern crate rand;
+extern crate regex;
 extern crate rustc_serialize;
+extern crate time;
 extern crate yaml_rust;
 
 pub mod spag;
@@ -5,13 +5,14 @@ use std;
 use std::io::prelude::*;
 use std::collections::hash_map::HashMap;
 
-use curl::http::handle::Method;
 use docopt::Docopt;
 use yaml_rust::Yaml;
 use yaml_rust::yaml::Hash;
 
 use super::request;
 use super::env;
+use super::extract;
+use super::file;
 use super::template;
 use super::yaml_util;
 
@@ -21,115 +22,295 @@ Usage:
     spag <command> [<args>...]
 
 Options:
-    -h --help       Show this message
+    -h --help               Show this message
+    --spag-dir <dir>        Use this .spag directory instead of searching for one. Also set by SPAG_DIR.
+    --env <name>            Use this environment instead of the active one. Also set by SPAG_ENV.
 
 Commands:
+    init            Create a .spag directory here
     env             Manage spag environments
     request         Send predefined request files
     history         View request history
-    <method>        Perform an HTTP request: get, post, patch, delete, etc
+    run             Send a sequence of request files
+    import          Create a request file from a curl command
+    <method>        Perform an HTTP request: get, post, put, patch, delete, head or options
+    send            Perform an HTTP request with any method, like PURGE
+");
+
+docopt!(pub InitArgs derive Debug, "
+Usage:
+    spag init [options]
+
+Options:
+    -h --help       Show this message
+
+Creates a .spag directory in the current directory, or at SPAG_DIR if it's set. Until then,
+spag uses the nearest .spag directory in the current directory or any of its parents.
 ");
 
 docopt!(pub EnvArgs derive Debug, "
 Usage:
     spag env --help
     spag env ls
-    spag env cat [<environment>]
+    spag env cat [<environment>] [--resolved]
     spag env activate <environment>
     spag env deactivate
-    spag env set (<key> <val>)...
+    spag env set (<key> <val>)... [--global] [--type <type>]
     spag env unset [(<key>)...] [-E]
+    spag env copy <environment> <new-environment>
+    spag env rename <environment> <new-environment>
+    spag env rm <environment> [--force]
+    spag env diff <environment> <other-environment>
+    spag env import --dotenv <file> [<environment>]
+    spag env export [<environment>] [--format <format>] [--show-secrets]
 
 Options:
     -h --help           Show this message
     -E --everything     Unset an entire environment
+    -f --force          Remove the environment even if it's active
+    --dotenv <file>     Import the values in a .env file, like FOO_BAR=x
+    --format <format>   Export as sh, dotenv, or json [default: sh]
+    --show-secrets      Export secret values instead of redacting them
+    -g --global         Set the values in your global environment, which applies to every project
+    -t --type <type>    Set the values as str, int, float, bool, or yaml for a list or map [default: str]
+    -r --resolved       Show the environment merged with the environments it extends
 
 Arguments:
-    <environment>       The name of an environment, like 'default'
-    <key>               The key name of a value to set, like 'headers.Content-type'
-    <val>               The value to set on the given key
+    <environment>           The name of an environment, like 'default'
+    <new-environment>       The name for the copied or renamed environment
+    <other-environment>     The environment to compare against
+    <key>                   The key name of a value to set, like 'headers.Content-type'
+    <val>                   The value to set on the given key
 ");
 
 docopt!(pub RequestArgs derive Debug, "
 Usage:
     spag (request|r) --help
     spag (request|r) ls [--dir <dir>]
+    spag (request|r) lint [--dir <dir>]
     spag (request|r) cat <file>
     spag (request|r) inspect <file>
-    spag (request|r) <file> [options] [(-H <header>)...] [(-w <key> <val>|--with <key> <val>)...]
+    spag (request|r) <file> [options] [(-H <header>)...] [(-q <param>)...] [(--set-env <extract>)...] [(-w <key> <val>|--with <key> <val>)...]
 
 Options:
     -h --help                   Show this message
     -H --header <header>        Supply a header
+    -q --param <param>          Supply a query parameter
     -e --endpoint <endpoint>    Supply the endpoint
     -d --data <data>            Supply the request body
     -v --verbose                Print out more of the request and response
     -r --remember-as <name>     Additionally, remember this request under the given name
+    --set-env <extract>         Save a value from the response into the active environment
     --dir <dir>                 The directory containing request files
+    --as-curl                   Print the request as a curl command instead of sending it
+    --dry-run                   Print the request instead of sending it
+    --prompt                    Ask for the values of missing parameters. This is the default if stdin is a terminal.
+    --no-prompt                 Never ask for the values of missing parameters
 
 Arguments:
     <endpoint>      The base url of the service, like 'http://localhost:5000'
     <header>        An http header, like 'Content-type: application/json'
+    <param>         A query parameter, like 'limit=10'
+    <extract>       An environment key and where to find its value, like 'token=body.token'
 ");
 
 docopt!(pub HistoryArgs derive Debug, "
 Usage:
     spag history [options]
-    spag history <index>
+    spag history <index> [--as-curl]
 
 Options:
     -h --help       Show this message
+    --as-curl       Print the request as a curl command
 
 Arguments:
     <index>         An index, starting at zero
 ");
 
+docopt!(pub RunArgs derive Debug, "
+Usage:
+    spag run --help
+    spag run <suite> [options]
+
+Options:
+    -h --help                   Show this message
+    -e --endpoint <endpoint>    Supply the endpoint
+    -v --verbose                Print out the response body of each step
+    --continue-on-error         Keep sending requests after a step fails
+    --dir <dir>                 The directory containing request files
+
+Arguments:
+    <suite>         A yaml file listing the request files to send, in order
+    <endpoint>      The base url of the service, like 'http://localhost:5000'
+");
+
+docopt!(pub ImportArgs derive Debug, "
+Usage:
+    spag import --help
+    spag import curl <command> (-s <name>|--save <name>) [options]
+
+Options:
+    -h --help               Show this message
+    -s --save <name>        The name of the request file to write
+    -f --force              Overwrite the request file if it already exists
+    --dir <dir>             The directory to write the request file in
+
+Arguments:
+    <command>       A curl command line, like 'curl -X POST http://localhost:5000/things -d ...'
+");
+
 docopt!(pub MethodArgs derive Debug, "
 Usage:
     spag <method> --help
-    spag <method> <path> [options] [(-H <header>)...]
+    spag <method> <path> [options] [(-H <header>)...] [(-q <param>)...] [(--set-env <extract>)...]
+    spag send <verb> <path> [options] [(-H <header>)...] [(-q <param>)...] [(--set-env <extract>)...]
 
 Options:
     -h --help                   Show this message
     -H --header <header>        Supply a header
+    -q --param <param>          Supply a query parameter
     -e --endpoint <endpoint>    Supply the endpoint
     -d --data <data>            Supply the request body
     -v --verbose                Print out more of the request and response
     -r --remember-as <name>     Remember this request under the given name
+    --set-env <extract>         Save a value from the response into the active environment
+    --dry-run                   Print the request instead of sending it
+    --no-template               Send the path, data, headers and params as is, without templating
 
 Arguments:
-    <method>        The http method: get, post, put, patch, delete
+    <method>        The http method: get, post, put, patch, delete, head or options
+    <verb>          The http method to send, like TRACE or PURGE
     <endpoint>      The base url of the service, like 'http://localhost:5000'
     <path>          The path of an api resource, like '/v2/things'
     <header>        An http header, like 'Content-type: application/json'
+    <param>         A query parameter, like 'limit=10'
+    <extract>       An environment key and where to find its value, like 'token=body.token'
 ");
 
 // I tried to find a nicer way to parse args *outside of this module*, but MainArgs::docopt() is
 // private, which means we never refer to it outside of this module. So we have a bunch of new
 // functions we can call into to do things for us.
 pub fn parse_main_args(args: &Vec<String>) -> MainArgs { parse_args!(MainArgs, args) }
+pub fn parse_init_args(args: &Vec<String>) -> InitArgs { parse_args!(InitArgs, args) }
 pub fn parse_env_args(args: &Vec<String>) -> EnvArgs { parse_args!(EnvArgs, args) }
 pub fn parse_request_args(args: &Vec<String>) -> RequestArgs { parse_args!(RequestArgs, args) }
 pub fn parse_method_args(args: &Vec<String>) -> MethodArgs { parse_args!(MethodArgs, args) }
 pub fn parse_history_args(args: &Vec<String>) -> HistoryArgs { parse_args!(HistoryArgs, args) }
+pub fn parse_run_args(args: &Vec<String>) -> RunArgs { parse_args!(RunArgs, args) }
+pub fn parse_import_args(args: &Vec<String>) -> ImportArgs { parse_args!(ImportArgs, args) }
+
+pub fn get_method_from_args(args: &MethodArgs) -> Result<String, String> {
+    if args.cmd_send {
+        request::method_from_str(&args.arg_verb)
+    } else {
+        request::method_from_str(&args.arg_method)
+    }
+}
+
+// The options that take values, and how many, for each command. These are skipped when looking
+// for a global flag, so that in "spag post /things --data --env" the data is "--env".
+const GLOBAL_VALUE_OPTIONS: &'static [(&'static str, usize)] = &[
+    ("--spag-dir", 1), ("--env", 1),
+];
+const ENV_VALUE_OPTIONS: &'static [(&'static str, usize)] = &[
+    ("--dotenv", 1), ("--format", 1), ("-t", 1), ("--type", 1),
+];
+const REQUEST_VALUE_OPTIONS: &'static [(&'static str, usize)] = &[
+    ("-H", 1), ("--header", 1), ("-q", 1), ("--param", 1), ("-e", 1), ("--endpoint", 1),
+    ("-d", 1), ("--data", 1), ("-r", 1), ("--remember-as", 1), ("--set-env", 1), ("--dir", 1),
+    ("-w", 2), ("--with", 2),
+];
+const RUN_VALUE_OPTIONS: &'static [(&'static str, usize)] = &[
+    ("-e", 1), ("--endpoint", 1), ("--dir", 1),
+];
+const IMPORT_VALUE_OPTIONS: &'static [(&'static str, usize)] = &[
+    ("-s", 1), ("--save", 1), ("--dir", 1),
+];
+const METHOD_VALUE_OPTIONS: &'static [(&'static str, usize)] = &[
+    ("-H", 1), ("--header", 1), ("-q", 1), ("--param", 1), ("-e", 1), ("--endpoint", 1),
+    ("-d", 1), ("--data", 1), ("-r", 1), ("--remember-as", 1), ("--set-env", 1),
+];
+
+/// How many values follow the option, given the command it's used with
+fn count_option_values(command: &str, option: &str) -> usize {
+    let options: &[(&str, usize)] = match command {
+        "env" => ENV_VALUE_OPTIONS,
+        "r" | "request" => REQUEST_VALUE_OPTIONS,
+        "run" => RUN_VALUE_OPTIONS,
+        "import" => IMPORT_VALUE_OPTIONS,
+        "get" | "post" | "put" | "patch" | "delete" | "head" | "options" | "send" => {
+            METHOD_VALUE_OPTIONS
+        },
+        _ => &[],
+    };
+    GLOBAL_VALUE_OPTIONS.iter().chain(options.iter())
+        .find(|&&(name, _)| name == option)
+        .map(|&(_, count)| count)
+        .unwrap_or(0)
+}
+
+/// Remove a flag like "--spag-dir <dir>" or "--spag-dir=<dir>" from the arguments, for flags that
+/// apply to every command. The flag can go before or after the command, but the values of other
+/// options and anything after "--" are left alone. Returns the rest of the arguments and the
+/// flag's value, if it was given.
+pub fn take_global_flag(argv: &Vec<String>, flag: &str) -> Result<(Vec<String>, Option<String>), String> {
+    let prefix = format!("{}=", flag);
+    let mut result: Vec<String> = Vec::new();
+    let mut value: Option<String> = None;
+    let mut command = "";
+    let mut args = argv.iter();
+    // the program name
+    if let Some(arg) = args.next() {
+        result.push(arg.to_string());
+    }
+    loop {
+        let arg = match args.next() {
+            Some(arg) => arg,
+            None => { break; },
+        };
+        if arg == "--" {
+            result.push(arg.to_string());
+            result.extend(args.cloned());
+            break;
+        } else if arg == flag {
+            match args.next() {
+                Some(v) => { value = Some(v.to_string()); },
+                None => { return Err(format!("Missing value for {}", flag)); },
+            }
+        } else if arg.starts_with(&prefix) {
+            value = Some(arg[prefix.len()..].to_string());
+        } else if arg.starts_with("-") {
+            result.push(arg.to_string());
+            for _ in 0..count_option_values(command, arg) {
+                if let Some(v) = args.next() {
+                    result.push(v.to_string());
+                }
+            }
+        } else {
+            if command.is_empty() {
+                command = arg.as_str();
+            }
+            result.push(arg.to_string());
+        }
+    }
+    Ok((result, value))
+}
 
-pub fn get_method_from_args(args: &MethodArgs) -> Method {
-    match args.arg_method.to_lowercase().as_str() {
-        "get" => Method::Get,
-        "post" => Method::Post,
-        "put" => Method::Put,
-        "patch" => Method::Patch,
-        "delete" => Method::Delete,
-        _ => { panic!("BUG: method not recognized"); },
+/// The environment to use for this run: the one given with --env, or else SPAG_ENV. An empty name
+/// means the active environment.
+pub fn get_env_name(flag_env: &Option<String>) -> String {
+    match *flag_env {
+        Some(ref name) => name.to_string(),
+        None => std::env::var("SPAG_ENV").unwrap_or(String::new()),
     }
 }
 
-pub fn get_endpoint(flag_endpoint: &str) -> Result<String, String> {
+pub fn get_endpoint(flag_endpoint: &str, env_name: &str) -> Result<String, String> {
     // passing -e ENDPOINT overrides everything else
     if !flag_endpoint.is_empty() {
         Ok(flag_endpoint.to_string())
     } else {
-        let env = try!(env::load_environment(""));
+        let env = try!(env::load_environment(env_name));
         if let Some(e) = env["endpoint"].as_str() {
             Ok(e.to_string())
         } else {
@@ -138,23 +319,24 @@ pub fn get_endpoint(flag_endpoint: &str) -> Result<String, String> {
     }
 }
 
-pub fn get_dir(args: &RequestArgs) -> Result<String, String> {
+pub fn get_dir(flag_dir: &str, env_name: &str) -> Result<String, String> {
     // passing in --dir overrides everything else
-    if !args.flag_dir.is_empty() {
-        Ok(args.flag_dir.to_string())
+    if !flag_dir.is_empty() {
+        Ok(flag_dir.to_string())
     } else {
-        let env = try!(env::load_environment(""));
+        let env = try!(env::load_environment(env_name));
         if let Some(e) = env["dir"].as_str() {
-            Ok(e.to_string())
+            Ok(file::project_path(e))
         } else {
             Err("Request directory not set".to_string())
         }
     }
 }
 
-pub fn get_data(flag_data: &str, withs: &HashMap<&str, &str>) -> Result<String, String> {
+pub fn get_data(flag_data: &str, withs: &HashMap<&str, &str>, env_name: &str
+                ) -> Result<String, String> {
     let use_shortcuts = true;
-    Ok(try!(template::untemplate(flag_data, &withs, use_shortcuts)))
+    Ok(try!(template::untemplate_body(flag_data, "--data", &withs, env_name, use_shortcuts)))
 }
 
 fn get_headers_from_request(request_yaml: &Yaml) -> Result<HashMap<String, String>, String> {
@@ -163,48 +345,51 @@ fn get_headers_from_request(request_yaml: &Yaml) -> Result<HashMap<String, Strin
     let request_file_headers = yaml_util::get_nested_value(&request_yaml, &["headers"]).unwrap_or(default_hash);
     if let &Yaml::Hash(ref h) = request_file_headers {
         for (k, v) in h.iter() {
-            if let (&Yaml::String(ref key), &Yaml::String(ref value)) = (k, v) {
-                result.insert(key.to_string(), value.to_string());
+            if let (&Yaml::String(ref key), Some(value)) = (k, yaml_util::scalar_to_string(v)) {
+                result.insert(key.to_string(), value);
             }
         }
     }
     Ok(result)
 }
 
-fn get_headers_from_environment() -> Result<HashMap<String, String>, String> {
+fn get_headers_from_environment(env_name: &str) -> Result<HashMap<String, String>, String> {
     let default_hash = &Yaml::Hash(Hash::new());
     let mut result: HashMap<String, String> = HashMap::new();
     // TODO: case insensitivity
     // be sure not to fail if we fail to load the env.
-    let env = env::load_environment("").unwrap_or(Yaml::Hash(Hash::new()));
+    let env = env::load_environment(env_name).unwrap_or(Yaml::Hash(Hash::new()));
     let env_headers = yaml_util::get_nested_value(&env, &["headers"]).unwrap_or(default_hash);
     if let &Yaml::Hash(ref h) = env_headers {
         for (k, v) in h.iter() {
-            if let (&Yaml::String(ref key), &Yaml::String(ref value)) = (k, v) {
-                result.insert(key.to_string(), value.to_string());
+            if let (&Yaml::String(ref key), Some(value)) = (k, yaml_util::scalar_to_string(v)) {
+                result.insert(key.to_string(), value);
             }
         }
     }
     Ok(result)
 }
 
-fn get_headers_from_args(flag_header: &Vec<String>) -> Result<HashMap<String, String>, String> {
+fn get_headers_from_args(flag_header: &Vec<String>, env_name: &str
+                         ) -> Result<HashMap<String, String>, String> {
     let use_shortcuts = true;
     let mut result: HashMap<String, String> = HashMap::new();
     let arg_headers: Vec<(&str, &str)> =
         try_error!(flag_header.iter().map(|s| request::split_header(s)).collect());
     for &(k, v) in arg_headers.iter() {
-        let v = try_error!(template::untemplate(&v, &HashMap::new(), use_shortcuts));
+        let v = try_error!(template::untemplate(&v, "--header", &HashMap::new(), env_name,
+                                                use_shortcuts));
         result.insert(k.to_string(), v.to_string());
     }
     Ok(result)
 }
 
 /// Build a single list of headers from the environment, the request yaml, and arguments.
-pub fn resolve_headers(arg_headers: &Vec<String>, request_yaml: &Yaml) -> Result<Vec<String>, String> {
+pub fn resolve_headers(arg_headers: &Vec<String>, request_yaml: &Yaml, env_name: &str
+                       ) -> Result<Vec<String>, String> {
     let request_headers = try!(get_headers_from_request(request_yaml));
-    let env_headers = try!(get_headers_from_environment());
-    let arg_headers = try!(get_headers_from_args(arg_headers));
+    let env_headers = try!(get_headers_from_environment(env_name));
+    let arg_headers = try!(get_headers_from_args(arg_headers, env_name));
     let mut result: HashMap<String, String> = HashMap::new();
     // start with headers in the environment
     result.extend(env_headers);
@@ -220,15 +405,123 @@ pub fn resolve_headers(arg_headers: &Vec<String>, request_yaml: &Yaml) -> Result
     Ok(str_headers)
 }
 
-pub fn resolve_headers_no_request_file(flag_header: &Vec<String>) -> Result<Vec<String>, String> {
-    resolve_headers(flag_header, &Yaml::Hash(Hash::new()))
+pub fn resolve_headers_no_request_file(flag_header: &Vec<String>, env_name: &str
+                                       ) -> Result<Vec<String>, String> {
+    resolve_headers(flag_header, &Yaml::Hash(Hash::new()), env_name)
+}
+
+fn get_params_from_request(request_yaml: &Yaml) -> Result<Vec<(String, String)>, String> {
+    let mut result: Vec<(String, String)> = Vec::new();
+    match yaml_util::get_nested_value(&request_yaml, &["params"]) {
+        Some(&Yaml::Hash(ref h)) => {
+            for (k, v) in h.iter() {
+                let key = try!(yaml_util::scalar_to_string(k)
+                    .ok_or(format!("Invalid query parameter name {:?}", yaml_util::show_yaml(k))));
+                // a list of values means the parameter is repeated, like "?a=1&a=2"
+                let values: Vec<&Yaml> =
+                    if let &Yaml::Array(ref arr) = v {
+                        arr.iter().collect()
+                    } else {
+                        vec![v]
+                    };
+                for value in values {
+                    let value = try!(yaml_util::scalar_to_string(value)
+                        .ok_or(format!("Invalid value {} for query parameter {:?}",
+                                       yaml_util::show_yaml(value), key)));
+                    result.push((key.to_string(), value));
+                }
+            }
+        },
+        None => {},
+        _ => { return Err("Invalid params in request file. Expected a map.".to_string()); },
+    }
+    Ok(result)
+}
+
+fn get_params_from_args(flag_param: &Vec<String>, env_name: &str
+                        ) -> Result<Vec<(String, String)>, String> {
+    let use_shortcuts = true;
+    let mut result: Vec<(String, String)> = Vec::new();
+    for param in flag_param.iter() {
+        let (k, v) = try!(request::split_param(param));
+        let v = try!(template::untemplate(&v, "--param", &HashMap::new(), env_name,
+                                          use_shortcuts));
+        result.push((k.to_string(), v));
+    }
+    Ok(result)
+}
+
+/// Build a single list of query parameters from the request yaml and arguments.
+/// Parameters from arguments are added after those in the request file. Nothing is overridden,
+/// so a key given in both places will be sent twice.
+pub fn resolve_params(flag_param: &Vec<String>, request_yaml: &Yaml, env_name: &str
+                      ) -> Result<Vec<(String, String)>, String> {
+    let mut result = try!(get_params_from_request(request_yaml));
+    result.extend(try!(get_params_from_args(flag_param, env_name)));
+    Ok(result)
+}
+
+pub fn resolve_params_no_request_file(flag_param: &Vec<String>, env_name: &str
+                                      ) -> Result<Vec<(String, String)>, String> {
+    resolve_params(flag_param, &Yaml::Hash(Hash::new()), env_name)
+}
+
+fn get_extractions_from_request(request_yaml: &Yaml) -> Result<Vec<(String, String)>, String> {
+    let mut result: Vec<(String, String)> = Vec::new();
+    match yaml_util::get_nested_value(&request_yaml, &["extract"]) {
+        Some(&Yaml::Hash(ref h)) => {
+            for (k, v) in h.iter() {
+                if let (&Yaml::String(ref key), &Yaml::String(ref source)) = (k, v) {
+                    result.push((key.to_string(), source.to_string()));
+                } else {
+                    return Err(format!("Invalid extract \"{}: {}\" in request file",
+                                       yaml_util::show_yaml(k), yaml_util::show_yaml(v)));
+                }
+            }
+        },
+        None => {},
+        _ => { return Err("Invalid extract in request file. Expected a map.".to_string()); },
+    }
+    Ok(result)
+}
+
+fn get_extractions_from_args(flag_set_env: &Vec<String>) -> Result<Vec<(String, String)>, String> {
+    let mut result: Vec<(String, String)> = Vec::new();
+    for extraction in flag_set_env.iter() {
+        match extraction.find('=') {
+            Some(i) if i > 0 => {
+                result.push((extraction[..i].to_string(), extraction[i + 1..].to_string()));
+            },
+            _ => { return Err(format!("Invalid --set-env {:?}. Expected 'key=path'", extraction)); },
+        }
+    }
+    Ok(result)
+}
+
+/// Build a list of (environment key, response value) pairs from the extract section of the
+/// request yaml and the --set-env arguments. Values are checked here, before sending a request.
+pub fn resolve_extractions(flag_set_env: &Vec<String>, request_yaml: &Yaml
+                           ) -> Result<Vec<(String, String)>, String> {
+    let mut result = try!(get_extractions_from_request(request_yaml));
+    result.extend(try!(get_extractions_from_args(flag_set_env)));
+    for &(_, ref source) in result.iter() {
+        try!(extract::source_to_key_path(source));
+    }
+    Ok(result)
+}
+
+pub fn resolve_extractions_no_request_file(flag_set_env: &Vec<String>
+                                           ) -> Result<Vec<(String, String)>, String> {
+    resolve_extractions(flag_set_env, &Yaml::Hash(Hash::new()))
 }
 
-pub fn get_withs(keys: &Vec<String>, vals: &Vec<String>) -> HashMap<String, String> {
+pub fn get_withs(keys: &Vec<String>, vals: &Vec<String>, env_name: &str) -> HashMap<String, String> {
     let use_shortcuts = true;
     let mut withs = HashMap::new();
     for (k, v) in keys.iter().zip(vals.iter()) {
-        let v = try_error!(template::untemplate(&v, &HashMap::new(), use_shortcuts));
+        // an error shows the key, since each value is given after one
+        let v = try_error!(template::untemplate(&v, k, &HashMap::new(), env_name,
+                                                use_shortcuts));
         withs.insert(k.to_string(), v.to_string());
     }
     withs
@@ -0,0 +1,95 @@
+/// Parse the text of a .env file into (line number, key, value), in order. This handles comments,
+/// blank lines, an optional "export " before the key, and single or double quoted values:
+///
+///     # the api
+///     export API_URL=http://localhost:5000
+///     GREETING="hello\nworld"   # escapes work in double quotes
+///     RAW='no $expansion here'
+pub fn parse_dotenv(text: &str, filename: &str) -> Result<Vec<(usize, String, String)>, String> {
+    let mut result: Vec<(usize, String, String)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+        let line = if line.starts_with("export ") { line[7..].trim_left() } else { line };
+        let (key, value) = match line.find('=') {
+            Some(index) => (line[..index].trim(), line[index + 1..].trim_left()),
+            None => ("", ""),
+        };
+        if key.is_empty() {
+            return Err(format!("{}:{}: Expected a line like KEY=value", filename, i + 1));
+        }
+        let value = try!(parse_value(value)
+            .map_err(|e| format!("{}:{}: {}", filename, i + 1, e)));
+        result.push((i + 1, key.to_string(), value));
+    }
+    Ok(result)
+}
+
+fn parse_value(value: &str) -> Result<String, String> {
+    if value.starts_with("'") {
+        match value[1..].find('\'') {
+            Some(index) => Ok(value[1..index + 1].to_string()),
+            None => Err(format!("Unclosed quote")),
+        }
+    } else if value.starts_with("\"") {
+        let mut result = String::new();
+        let mut chars = value[1..].chars();
+        loop {
+            match chars.next() {
+                Some('"') => { return Ok(result); },
+                Some('\\') => {
+                    match chars.next() {
+                        Some('n') => result.push('\n'),
+                        Some('t') => result.push('\t'),
+                        Some(c) => result.push(c),
+                        None => {},
+                    }
+                },
+                Some(c) => result.push(c),
+                None => { return Err(format!("Unclosed quote")); },
+            }
+        }
+    } else {
+        // an unquoted value ends at a comment
+        let end = value.find(" #").unwrap_or(value.len());
+        Ok(value[..end].trim_right().to_string())
+    }
+}
+
+/// Quote a value for a .env file, only if it's needed
+pub fn dotenv_quote(value: &str) -> String {
+    let plain = !value.is_empty() && value.chars().all(|c| {
+        match c {
+            'a' ... 'z' | 'A' ... 'Z' | '0' ... '9' => true,
+            c => "-_./:@,+%".contains(c),
+        }
+    });
+    if plain {
+        value.to_string()
+    } else {
+        let escaped = value.replace("\\", "\\\\").replace("\"", "\\\"").replace("\n", "\\n");
+        format!("\"{}\"", escaped)
+    }
+}
+
+/// Quote a value for a POSIX shell, like 'it'\''s'
+pub fn sh_quote(value: &str) -> String {
+    format!("'{}'", value.replace("'", "'\\''"))
+}
+
+/// Turn a key path into a shell variable name, like "headers.X-Auth" -> "headers_X_Auth"
+pub fn sh_variable_name(key: &str) -> String {
+    let name: String = key.chars()
+        .map(|c| match c {
+            'a' ... 'z' | 'A' ... 'Z' | '0' ... '9' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    if name.chars().next().map(|c| c >= '0' && c <= '9').unwrap_or(true) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::hash_map::HashMap;
+use std::ffi::{CStr, CString};
+use std::ptr;
+use std::slice;
+use std::str;
+
+use curl::http;
+use libc::{c_char, c_int, c_long, c_void, size_t};
+
+/// The curl bindings only send the standard http methods. This is just enough of libcurl's easy
+/// interface to send a request with any other method, like PURGE, using CURLOPT_CUSTOMREQUEST.
+pub fn send(method: &str, url: &str, headers: &HashMap<String, String>, body: &str
+            ) -> Result<http::Response, String> {
+    let method = try!(to_c_string(method));
+    let url = try!(to_c_string(url));
+    let mut header_lines: Vec<CString> = Vec::new();
+    for (k, v) in headers.iter() {
+        header_lines.push(try!(to_c_string(&format!("{}: {}", k, v))));
+    }
+
+    let mut resp_body: Vec<u8> = Vec::new();
+    let mut resp_headers: Vec<u8> = Vec::new();
+    let mut code: c_long = 0;
+    let mut easy = try!(Easy::new());
+    unsafe {
+        for line in header_lines.iter() {
+            // on failure curl leaves the list alone, so it's still freed when easy is dropped
+            let header_list = curl_slist_append(easy.header_list, line.as_ptr());
+            if header_list.is_null() {
+                return Err(format!("Failed to add the header {:?}",
+                                   String::from_utf8_lossy(line.as_bytes())));
+            }
+            easy.header_list = header_list;
+        }
+        try!(check(curl_easy_setopt(easy.handle, CURLOPT_NOSIGNAL, 1 as c_long)));
+        try!(check(curl_easy_setopt(easy.handle, CURLOPT_URL, url.as_ptr())));
+        try!(check(curl_easy_setopt(easy.handle, CURLOPT_CUSTOMREQUEST, method.as_ptr())));
+        try!(check(curl_easy_setopt(easy.handle, CURLOPT_HTTPHEADER, easy.header_list)));
+        if !body.is_empty() {
+            try!(check(curl_easy_setopt(easy.handle, CURLOPT_POSTFIELDSIZE, body.len() as c_long)));
+            try!(check(curl_easy_setopt(easy.handle, CURLOPT_POSTFIELDS, body.as_ptr())));
+        }
+        try!(check(curl_easy_setopt(easy.handle, CURLOPT_WRITEFUNCTION, write_to_vec as WriteFn)));
+        try!(check(curl_easy_setopt(easy.handle, CURLOPT_WRITEDATA,
+                                    &mut resp_body as *mut Vec<u8> as *mut c_void)));
+        try!(check(curl_easy_setopt(easy.handle, CURLOPT_HEADERFUNCTION, write_to_vec as WriteFn)));
+        try!(check(curl_easy_setopt(easy.handle, CURLOPT_HEADERDATA,
+                                    &mut resp_headers as *mut Vec<u8> as *mut c_void)));
+        try!(check(curl_easy_perform(easy.handle)));
+        try!(check(curl_easy_getinfo(easy.handle, CURLINFO_RESPONSE_CODE, &mut code as *mut c_long)));
+    }
+    Ok(http::Response::new(code as u32, parse_headers(&resp_headers), resp_body))
+}
+
+/// Parse the raw header lines of a response. Names are lower case, like the curl bindings give us.
+/// If there's more than one response, like after a "100 Continue", only the last one's are kept.
+fn parse_headers(raw: &[u8]) -> HashMap<String, Vec<String>> {
+    let text = String::from_utf8_lossy(raw);
+    let mut result: HashMap<String, Vec<String>> = HashMap::new();
+    for line in text.lines() {
+        if line.starts_with("HTTP/") {
+            result.clear();
+        } else if let Some(i) = line.find(':') {
+            let name = line[..i].trim().to_lowercase();
+            result.entry(name).or_insert(Vec::new()).push(line[i + 1..].trim().to_string());
+        }
+    }
+    result
+}
+
+fn to_c_string(s: &str) -> Result<CString, String> {
+    CString::new(s).map_err(|_| format!("Invalid null character in {:?}", s))
+}
+
+fn check(code: c_int) -> Result<(), String> {
+    if code == 0 {
+        return Ok(());
+    }
+    let message = unsafe { CStr::from_ptr(curl_easy_strerror(code)) };
+    Err(str::from_utf8(message.to_bytes()).unwrap_or("Unknown curl error").to_string())
+}
+
+type WriteFn = extern "C" fn(*const c_char, size_t, size_t, *mut c_void) -> size_t;
+
+/// Append the data curl gives us to the Vec<u8> that was set as the callback's data
+extern "C" fn write_to_vec(data: *const c_char, size: size_t, count: size_t, buf: *mut c_void
+                           ) -> size_t {
+    let len = size * count;
+    unsafe {
+        let buf = &mut *(buf as *mut Vec<u8>);
+        buf.extend(slice::from_raw_parts(data as *const u8, len as usize).iter().cloned());
+    }
+    len
+}
+
+/// A curl handle and the header list it sends, which are cleaned up when it goes out of scope
+struct Easy {
+    handle: *mut c_void,
+    header_list: *mut c_void,
+}
+
+impl Easy {
+    fn new() -> Result<Easy, String> {
+        let handle = unsafe { curl_easy_init() };
+        if handle.is_null() {
+            Err("Failed to start a curl session".to_string())
+        } else {
+            Ok(Easy { handle: handle, header_list: ptr::null_mut() })
+        }
+    }
+}
+
+impl Drop for Easy {
+    fn drop(&mut self) {
+        unsafe {
+            curl_slist_free_all(self.header_list);
+            curl_easy_cleanup(self.handle);
+        }
+    }
+}
+
+const CURLOPT_WRITEDATA: c_int = 10001;
+const CURLOPT_URL: c_int = 10002;
+const CURLOPT_POSTFIELDS: c_int = 10015;
+const CURLOPT_HTTPHEADER: c_int = 10023;
+const CURLOPT_HEADERDATA: c_int = 10029;
+const CURLOPT_CUSTOMREQUEST: c_int = 10036;
+const CURLOPT_POSTFIELDSIZE: c_int = 60;
+const CURLOPT_NOSIGNAL: c_int = 99;
+const CURLOPT_WRITEFUNCTION: c_int = 20011;
+const CURLOPT_HEADERFUNCTION: c_int = 20079;
+const CURLINFO_RESPONSE_CODE: c_int = 0x200002;
+
+// libcurl is linked by the curl bindings
+extern {
+    fn curl_easy_init() -> *mut c_void;
+    fn curl_easy_setopt(handle: *mut c_void, option: c_int, ...) -> c_int;
+    fn curl_easy_perform(handle: *mut c_void) -> c_int;
+    fn curl_easy_getinfo(handle: *mut c_void, info: c_int, ...) -> c_int;
+    fn curl_easy_cleanup(handle: *mut c_void);
+    fn curl_easy_strerror(code: c_int) -> *const c_char;
+    fn curl_slist_append(list: *mut c_void, s: *const c_char) -> *mut c_void;
+    fn curl_slist_free_all(list: *mut c_void);
+}
@@ -1,41 +1,88 @@
 use std;
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 use std::fs::PathExt;
+use std::collections::BTreeMap;
 
+use rustc_serialize::json::Json;
 use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
 
+use super::dotenv;
 use super::file;
+use super::secrets;
+use super::secrets::Secrets;
 use super::yaml_util;
 
-const ENV_DIR: &'static str          = ".spag/environments";
-const ACTIVE_ENV_FILE: &'static str  = ".spag/environments/active";
 const DEFAULT_ENV_NAME: &'static str = "default";
 
+/// Where an environment is kept. Global environments apply to every project, and are layered
+/// underneath the project's environment of the same name.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Scope {
+    Project,
+    Global,
+}
+
+impl Scope {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Scope::Project => "project",
+            Scope::Global => "global",
+        }
+    }
+
+    fn env_dir(&self) -> String {
+        match *self {
+            Scope::Project => env_dir(),
+            Scope::Global => global_env_dir(),
+        }
+    }
+}
+
+fn env_dir() -> String {
+    file::spag_path("environments")
+}
+
+/// Global environments are kept in $XDG_CONFIG_HOME/spag/environments, which defaults to
+/// ~/.config/spag/environments
+fn global_env_dir() -> String {
+    let config_dir = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => std::env::home_dir().unwrap_or(PathBuf::from(".")).join(".config"),
+    };
+    config_dir.join("spag").join("environments").to_str().unwrap().to_string()
+}
+
+fn active_env_file() -> String {
+    file::spag_path("environments/active")
+}
+
 /// Creates the active environment file, and the default environment file if they don't exist.
 /// Returns the name of the active environment, read from the active environment file.
 pub fn get_active_environment_name() -> String {
     // create file specifiying the active env if it doesn't exist
-    if !Path::new(ACTIVE_ENV_FILE).exists() {
-        file::ensure_dir_exists(ENV_DIR);
-        file::write_file(ACTIVE_ENV_FILE, DEFAULT_ENV_NAME);
+    let active_env_file = active_env_file();
+    if !Path::new(&active_env_file).exists() {
+        file::ensure_dir_exists(&env_dir());
+        file::write_file(&active_env_file, DEFAULT_ENV_NAME);
     }
     // create the default environment if it doesn't exist
-    let default_file = &format!("{}/{}", ENV_DIR, file::ensure_extension(DEFAULT_ENV_NAME, "yml"));
+    let default_file = &format!("{}/{}", env_dir(), file::ensure_extension(DEFAULT_ENV_NAME, "yml"));
     if !Path::new(default_file).exists() {
         file::write_file(default_file, "{}");
     }
-    try_error!(file::read_file(ACTIVE_ENV_FILE))
+    try_error!(file::read_file(&active_env_file))
 }
 
 /// Writes to the active environment file the name of the supplied environment, if it exists.
 pub fn set_active_environment(name: &str) -> Result<(), String> {
-    let env_filename = &format!("{}/{}", ENV_DIR, file::ensure_extension(name, "yml"));
-    if !Path::new(env_filename).exists() {
+    if get_environment_filename(name).is_err() {
         return Err(format!("Tried to activate non-existent environment {:?}", name))
     }
     // write out new name to the active file
-    file::write_file(ACTIVE_ENV_FILE, name);
+    file::write_file(&active_env_file(), name);
     Ok(())
 }
 
@@ -49,54 +96,233 @@ pub fn deactivate_environment() -> Result<(), String> {
     Ok(())
 }
 
-/// Returns a YAML object of the environment file requested.
-/// If the environment doesn't exist, it creates it.
+/// Returns a YAML object of the environment requested, merged with any environments it extends.
+/// The global environment of the same name, if there is one, is merged in underneath.
 pub fn load_environment(name: &str) -> Result<Yaml, String> {
-    let filename = &try!(get_environment_filename(name));
-    if !Path::new(filename).exists() {
-        file::write_file(filename, "{}");
+    let name = resolve_name(name);
+    let mut result: Option<Yaml> = None;
+    for scope in [Scope::Global, Scope::Project].iter() {
+        if let Some(filename) = try!(find_environment_file(&name, *scope)) {
+            let y = try!(yaml_util::load_yaml_file(&filename));
+            let y = try!(resolve_extends(y, *scope, &mut vec![filename.to_string()]));
+            result = Some(match result.take() {
+                Some(mut base) => { merge_environment(&mut base, &y); base },
+                None => y,
+            });
+        }
     }
-    yaml_util::load_yaml_file(filename)
+    result.ok_or(format!("Environment not found"))
 }
 
-/// Returns the filename for the request environment.
-/// If an empty string is passed, the active environment filename is returned.
-/// Handles ambiguous environment names.
-fn get_environment_filename(name: &str) -> Result<String, String> {
-    let name =
-        if name.is_empty() {
-            get_active_environment_name()
-        } else {
-            name.to_string()
+/// Follow the chain of "extends: <environment>" keys, merging each parent environment underneath
+/// its child. The filenames of environments already in the chain are kept to catch cycles.
+/// A project environment may extend a global one, but not the other way around.
+fn resolve_extends(y: Yaml, scope: Scope, chain: &mut Vec<String>) -> Result<Yaml, String> {
+    let parent_name =
+        match yaml_util::get_nested_value(&y, &["extends"]) {
+            None => { return Ok(y); },
+            Some(&Yaml::String(ref name)) => name.to_string(),
+            Some(other) => {
+                return Err(format!("Invalid value {:?} for extends in {}. Expected an environment name.",
+                                   other, chain[chain.len() - 1]));
+            },
         };
-    let filename = file::ensure_extension(&name, ".yml");
-    let paths = try!(file::find_matching_files(&filename, ".spag/environments"));
-    if paths.is_empty() {
-        Err(format!("Environment not found"))
-    } else if paths.len() >= 2 {
+    let parent_filename = try!(find_extended_file(&parent_name, scope)
+        .and_then(|f| f.ok_or(format!("Environment not found")))
+        .map_err(|e| format!("Failed to load environment {:?} extended by {} -- {}",
+                             parent_name, chain[chain.len() - 1], e)));
+    if chain.contains(&parent_filename) {
+        chain.push(parent_filename);
+        return Err(format!("Found a cycle of environments extending each other: {}", chain.join(" -> ")));
+    }
+    chain.push(parent_filename.to_string());
+
+    let parent = try!(yaml_util::load_yaml_file(&parent_filename));
+    let mut result = try!(resolve_extends(parent, scope, chain));
+    merge_environment(&mut result, &y);
+    yaml_util::unset_nested_value(&mut result, &["extends"]);
+    Ok(result)
+}
+
+/// Merge an environment over the one underneath it, like a child over the environment it extends
+/// or a project environment over the global one. The secrets listed in both are kept, since a
+/// list would otherwise replace the one underneath and leave its values unredacted.
+fn merge_environment(base: &mut Yaml, overrides: &Yaml) {
+    let mut key_paths = secrets::secret_key_paths(base);
+    let override_key_paths = secrets::secret_key_paths(overrides);
+    yaml_util::merge(base, overrides);
+    if key_paths.is_empty() || override_key_paths.is_empty() {
+        return;
+    }
+    for key_path in override_key_paths {
+        if !key_paths.contains(&key_path) {
+            key_paths.push(key_path);
+        }
+    }
+    if let Yaml::Hash(ref mut h) = *base {
+        let key_paths: Vec<Yaml> = key_paths.into_iter().map(Yaml::String).collect();
+        h.insert(Yaml::String("secrets".to_string()), Yaml::Array(key_paths));
+    }
+}
+
+/// Returns the filename of the environment named by "extends: <name>" in an environment in the
+/// given scope, if it exists
+fn find_extended_file(name: &str, scope: Scope) -> Result<Option<String>, String> {
+    match scope {
+        Scope::Project => get_environment_filename(name).map(|f| Some(f)),
+        Scope::Global => find_environment_file(name, Scope::Global),
+    }
+}
+
+/// Returns the filenames of the environments that extend the environment in the given file
+fn find_extending_files(filename: &str) -> Result<Vec<String>, String> {
+    let mut result: Vec<String> = Vec::new();
+    for scope in [Scope::Project, Scope::Global].iter() {
+        let dir = scope.env_dir();
+        if !Path::new(&dir).is_dir() {
+            continue;
+        }
+        for path in try!(file::walk_dir(&dir)) {
+            let child = path.to_str().unwrap().to_string();
+            if !child.ends_with(".yml") || child == filename {
+                continue;
+            }
+            // an environment that doesn't load can't be extending this one
+            let y = match yaml_util::load_yaml_file(&child) {
+                Ok(y) => y,
+                Err(_) => { continue; },
+            };
+            if let Some(parent_name) = y["extends"].as_str() {
+                let parent = find_extended_file(parent_name, *scope).ok().and_then(|f| f);
+                if parent.as_ref().map(|f| f.as_str()) == Some(filename) {
+                    result.push(child);
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// An empty name means the active environment
+fn resolve_name(name: &str) -> String {
+    if !name.is_empty() {
+        name.to_string()
+    } else {
+        get_active_environment_name()
+    }
+}
+
+/// Returns the filename for the environment in the given scope, if it exists there.
+/// Handles ambiguous environment names.
+fn find_environment_file(name: &str, scope: Scope) -> Result<Option<String>, String> {
+    let dir = scope.env_dir();
+    if !Path::new(&dir).is_dir() {
+        return Ok(None);
+    }
+    let filename = file::ensure_extension(name, ".yml");
+    let paths = try!(file::find_matching_files(&filename, &dir));
+    if paths.len() >= 2 {
         Err(format!("Ambiguous environment name. Pick one of {:?}", paths))
     } else {
-        Ok(paths[0].to_str().unwrap().to_string())
+        Ok(paths.first().map(|p| p.to_str().unwrap().to_string()))
+    }
+}
+
+/// Returns the filename for the request environment, from the project if it's there, or else the
+/// global environments. If an empty string is passed, the active environment filename is returned.
+fn get_environment_filename(name: &str) -> Result<String, String> {
+    let name = resolve_name(name);
+    if let Some(filename) = try!(find_environment_file(&name, Scope::Project)) {
+        return Ok(filename);
     }
+    match try!(find_environment_file(&name, Scope::Global)) {
+        Some(filename) => Ok(filename),
+        None => Err(format!("Environment not found")),
+    }
+}
+
+/// Returns the filename of the active environment, if it exists
+fn get_active_environment_filename() -> Option<String> {
+    get_environment_filename(&get_active_environment_name()).ok()
+}
+
+/// Returns the filename for the environment in the given scope, creating it if needed. Global
+/// environments can always be created this way, but a project environment only if the
+/// environment already exists globally.
+fn get_or_create_environment_file(name: &str, scope: Scope) -> Result<String, String> {
+    let name = resolve_name(name);
+    if let Some(filename) = try!(find_environment_file(&name, scope)) {
+        return Ok(filename);
+    }
+    if scope == Scope::Project {
+        try!(get_environment_filename(&name));
+    }
+    let dir = scope.env_dir();
+    file::ensure_dir_exists(&dir);
+    let filename = format!("{}/{}", dir, file::ensure_extension(&name, "yml"));
+    file::write_file(&filename, "{}");
+    Ok(filename)
 }
 
 /// Print out the given environment. If name is empty, use the active environment.
-/// The name will be fixed to end with '.yml'
+/// The name will be fixed to end with '.yml'. Secret values are redacted.
 pub fn show_environment(name: &str) -> Result<(), String> {
-    file::ensure_dir_exists(ENV_DIR);
+    file::ensure_dir_exists(&env_dir());
     let filename = try!(get_environment_filename(name));
-    println!("{}", try!(file::read_file(&filename)));
+    show_environment_file(name, &filename)
+}
+
+/// Print out the global environment of the given name, like show_environment()
+pub fn show_global_environment(name: &str) -> Result<(), String> {
+    let filename = try!(get_or_create_environment_file(name, Scope::Global));
+    show_environment_file(name, &filename)
+}
+
+fn show_environment_file(name: &str, filename: &str) -> Result<(), String> {
+    let text = try!(file::read_file(filename));
+    let raw = yaml_util::load_yaml_string(&text).unwrap_or(Yaml::Null);
+    // secrets may be listed in an environment this one extends, but a broken extends chain
+    // shouldn't stop us from showing the file. Fall back to the secrets listed in this file.
+    let secrets =
+        match load_environment(name) {
+            Ok(y) => Secrets::from_environment(&y),
+            Err(_) => Secrets::from_environment(&raw),
+        };
+    if secrets.is_empty() {
+        println!("{}", text);
+    } else {
+        let mut y = raw;
+        try!(secrets.redact_environment(&mut y));
+        println!("{}", try!(yaml_util::dump_yaml_string(&y)));
+    }
+    Ok(())
+}
+
+/// Print out the given environment merged with the environments it extends.
+/// Secret values are redacted.
+pub fn show_resolved_environment(name: &str) -> Result<(), String> {
+    file::ensure_dir_exists(&env_dir());
+    let mut y = try!(load_environment(name));
+    try!(Secrets::from_environment(&y).redact_environment(&mut y));
+    println!("{}", try!(yaml_util::dump_yaml_string(&y)));
     Ok(())
 }
 
+/// Print the filename of each environment, along with whether it's a project or global one
 pub fn list_environments() -> Result<(), String> {
-    file::ensure_dir_exists(ENV_DIR);
-    let mut environments = try!(file::walk_dir(ENV_DIR));
-    environments.sort();
-    for filename in environments {
-        let filename = filename.to_str().unwrap();
-        if filename.ends_with(".yml") {
-            println!("{}", filename);
+    file::ensure_dir_exists(&env_dir());
+    for scope in [Scope::Project, Scope::Global].iter() {
+        let dir = scope.env_dir();
+        if !Path::new(&dir).is_dir() {
+            continue;
+        }
+        let mut environments = try!(file::walk_dir(&dir));
+        environments.sort();
+        for filename in environments {
+            let filename = filename.to_str().unwrap();
+            if filename.ends_with(".yml") {
+                println!("{:<8}{}", scope.name(), filename);
+            }
         }
     }
     Ok(())
@@ -109,13 +335,20 @@ pub fn list_environments() -> Result<(), String> {
 ///         -> default["mini"] = "wumbo"
 pub fn set_in_environment(name: &str, keys: &Vec<String>, vals: &Vec<String>
                           ) -> Result<(), String> {
-    file::ensure_dir_exists(ENV_DIR);
-    let filename = try!(get_environment_filename(name));
+    let vals: Vec<Yaml> = vals.iter().map(|v| Yaml::String(v.to_string())).collect();
+    set_yaml_in_environment(name, Scope::Project, keys, &vals)
+}
+
+/// Like set_in_environment(), but for values of any type, in the environment in the given scope
+pub fn set_yaml_in_environment(name: &str, scope: Scope, keys: &Vec<String>, vals: &Vec<Yaml>
+                               ) -> Result<(), String> {
+    file::ensure_dir_exists(&env_dir());
+    let filename = try!(get_or_create_environment_file(name, scope));
     let mut y = try!(yaml_util::load_yaml_file(&filename));
 
     for (k, v) in keys.iter().zip(vals.iter()) {
         let parts: Vec<&str> = k.split('.').collect();
-        yaml_util::set_nested_value(&mut y, parts.as_slice(), &v);
+        try!(yaml_util::set_nested_yaml(&mut y, parts.as_slice(), v.clone()));
     }
 
     Ok(try!(yaml_util::dump_yaml_file(&filename, &y)))
@@ -127,7 +360,7 @@ pub fn set_in_environment(name: &str, keys: &Vec<String>, vals: &Vec<String>
 ///         -> default["a"]["b"]["c"] = None
 ///         -> default["wumbo"] = None
 pub fn unset_in_environment(name: &str, keys: &Vec<String>) -> Result<(), String> {
-    file::ensure_dir_exists(ENV_DIR);
+    file::ensure_dir_exists(&env_dir());
     let filename = try!(get_environment_filename(name));
     let mut y = try!(yaml_util::load_yaml_file(&filename));
 
@@ -141,9 +374,229 @@ pub fn unset_in_environment(name: &str, keys: &Vec<String>) -> Result<(), String
 
 /// Empties the environment. Unsets all values.
 pub fn unset_all_environment(name: &str) -> Result<(), String> {
-    file::ensure_dir_exists(ENV_DIR);
+    file::ensure_dir_exists(&env_dir());
     let filename = try!(get_environment_filename(name));
 
     file::write_file(&filename, "---\n{}");
     Ok(())
 }
+
+/// Set the values from a .env file in an environment, creating the project environment if it
+/// doesn't exist. Keys like "headers.Accept" are nested like they are for "spag env set".
+/// Nothing is written if any of the values can't be set.
+pub fn import_dotenv(name: &str, filename: &str) -> Result<(), String> {
+    file::ensure_dir_exists(&env_dir());
+    let values = try!(dotenv::parse_dotenv(&try!(file::read_file(filename)), filename));
+    let name = resolve_name(name);
+    let (env_filename, mut y) =
+        match try!(find_environment_file(&name, Scope::Project)) {
+            Some(f) => {
+                let y = try!(yaml_util::load_yaml_file(&f));
+                (f, y)
+            },
+            None => {
+                let f = format!("{}/{}", env_dir(), file::ensure_extension(&name, "yml"));
+                (f, Yaml::Hash(Hash::new()))
+            },
+        };
+    for &(line, ref key, ref value) in values.iter() {
+        let parts: Vec<&str> = key.split('.').collect();
+        try!(yaml_util::set_nested_yaml(&mut y, &parts, Yaml::String(value.to_string()))
+            .map_err(|e| format!("{}:{}: {}", filename, line, e)));
+    }
+    yaml_util::dump_yaml_file(&env_filename, &y)
+}
+
+/// Print the environment with flattened key paths, after resolving extends and global
+/// environments, so that other tools can use it. Secret values are redacted, with a warning for
+/// each one, unless show_secrets is given.
+pub fn export_environment(name: &str, format: &str, show_secrets: bool) -> Result<(), String> {
+    file::ensure_dir_exists(&env_dir());
+    let mut y = try!(load_environment(name));
+    if !show_secrets {
+        for key in try!(Secrets::from_environment(&y).redact_environment(&mut y)) {
+            printerrln!("Warning: the secret {:?} was exported as {}. Use --show-secrets to export \
+                         its value.", key, secrets::REDACTED);
+        }
+    }
+    for line in try!(format_environment(&y, format)) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Format the values of an environment by key path, as one of:
+///
+///     sh:     export headers_Accept='application/json'
+///     dotenv: headers.Accept=application/json
+///     json:   {"headers.Accept": "application/json"}
+///
+/// The list of secrets is left out, and lists are written as json.
+pub fn format_environment(y: &Yaml, format: &str) -> Result<Vec<String>, String> {
+    let mut values: Vec<(String, &Yaml)> = Vec::new();
+    flatten(y, "", &mut values);
+    values.retain(|&(ref k, _)| k != "secrets");
+
+    match format {
+        "sh" => {
+            Ok(values.iter()
+                .map(|&(ref k, v)| format!("export {}={}", dotenv::sh_variable_name(k),
+                                           dotenv::sh_quote(&yaml_util::show_yaml(v))))
+                .collect())
+        },
+        "dotenv" => {
+            Ok(values.iter()
+                .map(|&(ref k, v)| {
+                    format!("{}={}", k, dotenv::dotenv_quote(&yaml_util::show_yaml(v)))
+                })
+                .collect())
+        },
+        "json" => {
+            let mut result = BTreeMap::new();
+            for &(ref k, v) in values.iter() {
+                result.insert(k.to_string(), yaml_util::yaml_to_json(v).unwrap_or(Json::Null));
+            }
+            Ok(vec![format!("{}", Json::Object(result).pretty())])
+        },
+        _ => Err(format!("Unknown export format {:?}. Expected one of sh, dotenv or json", format)),
+    }
+}
+
+/// Copy an environment into a new project environment
+pub fn copy_environment(name: &str, new_name: &str) -> Result<(), String> {
+    file::ensure_dir_exists(&env_dir());
+    let filename = try!(get_environment_filename(name));
+    if try!(find_environment_file(new_name, Scope::Project)).is_some() {
+        return Err(format!("Environment {:?} already exists", new_name));
+    }
+    let contents = try!(file::read_file(&filename));
+    let new_filename = format!("{}/{}", env_dir(), file::ensure_extension(new_name, "yml"));
+    file::write_file(&new_filename, &contents);
+    Ok(())
+}
+
+/// Rename an environment, keeping it in the same scope. If it's the active environment, the new
+/// name becomes the active environment. Environments that extend it are changed to extend the
+/// new name.
+pub fn rename_environment(name: &str, new_name: &str) -> Result<(), String> {
+    file::ensure_dir_exists(&env_dir());
+    let filename = try!(get_environment_filename(name));
+    if get_environment_filename(new_name).is_ok() {
+        return Err(format!("Environment {:?} already exists", new_name));
+    }
+    // find these before renaming, while the old name still points at the file
+    let is_active = get_active_environment_filename() == Some(filename.to_string());
+    let children = try!(find_extending_files(&filename));
+
+    let new_filename = Path::new(&filename).with_file_name(file::ensure_extension(new_name, "yml"));
+    try!(std::fs::rename(&filename, &new_filename)
+        .map_err(|e| format!("Failed to rename environment {:?} -- {}", name, e)));
+    for child in children.iter() {
+        let mut y = try!(yaml_util::load_yaml_file(child));
+        try!(yaml_util::set_nested_value(&mut y, &["extends"], new_name));
+        try!(yaml_util::dump_yaml_file(child, &y));
+    }
+    if is_active {
+        try!(set_active_environment(new_name));
+    }
+    Ok(())
+}
+
+/// Delete an environment. The active environment, or the selected one given with --env or
+/// SPAG_ENV, is only deleted when forced. If the active environment is deleted, the default
+/// environment becomes active.
+pub fn remove_environment(name: &str, selected: &str, force: bool) -> Result<(), String> {
+    file::ensure_dir_exists(&env_dir());
+    let filename = try!(get_environment_filename(name));
+    let is_active = get_active_environment_filename() == Some(filename.to_string());
+    let is_selected = !selected.is_empty()
+        && get_environment_filename(selected).ok() == Some(filename.to_string());
+    if is_active && !force {
+        return Err(format!("Refusing to remove the active environment {:?}. Use --force to remove it \
+                            anyway.", name));
+    }
+    if is_selected && !force {
+        return Err(format!("Refusing to remove the selected environment {:?}. Use --force to \
+                            remove it anyway.", name));
+    }
+    try!(std::fs::remove_file(&filename)
+        .map_err(|e| format!("Failed to remove environment {:?} -- {}", name, e)));
+    if is_active {
+        try!(deactivate_environment());
+    }
+    Ok(())
+}
+
+/// Print the differences between two environments, after resolving extends and global
+/// environments. Secret values are redacted.
+pub fn show_environment_diff(name: &str, other_name: &str) -> Result<(), String> {
+    file::ensure_dir_exists(&env_dir());
+    let a = try!(load_environment(name).map_err(|e| format!("{} -- {:?}", e, name)));
+    let b = try!(load_environment(other_name).map_err(|e| format!("{} -- {:?}", e, other_name)));
+    // redact after comparing, so that a changed secret still shows up as changed
+    let secrets = [Secrets::from_environment(&a), Secrets::from_environment(&b)];
+    let show = |key: &str, y: &Yaml| {
+        let value = yaml_util::show_yaml(y);
+        if secrets.iter().any(|s| s.is_secret_key(key) || s.is_secret_value(&value)) {
+            secrets::REDACTED.to_string()
+        } else {
+            value
+        }
+    };
+    for line in format_diff(&a, &b, &show) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Compare two environments by nested key path. Returns a line for each key that was added
+/// ("+ key: value"), removed ("- key: value"), or changed ("~ key: old -> new") going from a
+/// to b, sorted by key path.
+pub fn diff_environments(a: &Yaml, b: &Yaml) -> Vec<String> {
+    format_diff(a, b, &|_: &str, y: &Yaml| yaml_util::show_yaml(y))
+}
+
+/// Like diff_environments(), showing each value with show(key_path, value)
+fn format_diff(a: &Yaml, b: &Yaml, show: &Fn(&str, &Yaml) -> String) -> Vec<String> {
+    let mut a_values: Vec<(String, &Yaml)> = Vec::new();
+    let mut b_values: Vec<(String, &Yaml)> = Vec::new();
+    flatten(a, "", &mut a_values);
+    flatten(b, "", &mut b_values);
+
+    let mut keys: Vec<&String> = a_values.iter().chain(b_values.iter()).map(|&(ref k, _)| k).collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut result: Vec<String> = Vec::new();
+    for key in keys {
+        let a_value = a_values.iter().find(|&&(ref k, _)| k == key).map(|&(_, v)| v);
+        let b_value = b_values.iter().find(|&&(ref k, _)| k == key).map(|&(_, v)| v);
+        match (a_value, b_value) {
+            (Some(a), Some(b)) if a != b => {
+                result.push(format!("~ {}: {} -> {}", key, show(key.as_str(), a), show(key.as_str(), b)));
+            },
+            (Some(a), None) => { result.push(format!("- {}: {}", key, show(key.as_str(), a))); },
+            (None, Some(b)) => { result.push(format!("+ {}: {}", key, show(key.as_str(), b))); },
+            _ => {},
+        }
+    }
+    result
+}
+
+/// Collect the values in nested maps along with their key paths, like ("headers.Accept", value)
+fn flatten<'a>(y: &'a Yaml, prefix: &str, result: &mut Vec<(String, &'a Yaml)>) {
+    match *y {
+        Yaml::Hash(ref h) if !h.is_empty() || prefix.is_empty() => {
+            for (k, v) in h.iter() {
+                let k = yaml_util::scalar_to_string(k).unwrap_or(format!("{:?}", k));
+                let key_path = if prefix.is_empty() { k } else { format!("{}.{}", prefix, k) };
+                flatten(v, &key_path, result);
+            }
+        },
+        _ => {
+            if !prefix.is_empty() {
+                result.push((prefix.to_string(), y));
+            }
+        },
+    }
+}
@@ -0,0 +1,247 @@
+use std::str;
+
+use curl::http;
+use regex::Regex;
+use rustc_serialize::json::Json;
+use yaml_rust::Yaml;
+
+use super::remember;
+use super::yaml_util;
+
+/// The order expectations are checked and reported in
+const EXPECTATION_KEYS: [&'static str; 5] =
+    ["status", "headers", "body", "body_matches", "body_contains"];
+
+/// The result of checking a single expectation against a response
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub passed: bool,
+    pub message: String,
+}
+
+impl Outcome {
+    fn new(passed: bool, message: String) -> Outcome {
+        Outcome { passed: passed, message: message }
+    }
+}
+
+/// Check a response against the expect section of a request file, which looks like:
+///
+///     expect:
+///         status: 2xx
+///         headers:
+///             Content-Type: application/json
+///             Location:
+///         body:
+///             things.0.id: wumbo
+///         body_matches:
+///             things.0.id: "^wum"
+///         body_contains: wumbo
+///
+/// Returns an Outcome for each expectation, or an error if the expect section is malformed.
+pub fn check(expect: &Yaml, resp: &http::Response) -> Result<Vec<Outcome>, String> {
+    let body = str::from_utf8(resp.get_body()).unwrap_or("");
+    check_response(expect, resp.get_code(), &get_first_headers(resp), body)
+}
+
+/// Like check(), but with the pieces of the response already pulled out
+pub fn check_response(expect: &Yaml, status: u32, headers: &Vec<(String, String)>, body: &str
+                      ) -> Result<Vec<Outcome>, String> {
+    if let &Yaml::Hash(ref h) = expect {
+        for key in h.keys() {
+            match key.as_str() {
+                Some(k) if EXPECTATION_KEYS.iter().any(|&e| e == k) => {},
+                _ => {
+                    return Err(format!("Unknown expectation {:?}", yaml_util::show_yaml(key)));
+                },
+            }
+        }
+    } else {
+        return Err("Invalid expect section in request file. Expected a map.".to_string());
+    }
+
+    let mut result: Vec<Outcome> = Vec::new();
+    for key in EXPECTATION_KEYS.iter() {
+        let value = match yaml_util::get_nested_value(expect, &[*key]) {
+            Some(v) => v,
+            None => { continue; },
+        };
+        match *key {
+            "status" => { result.push(try!(check_status(value, status))); },
+            "headers" => { result.extend(try!(check_headers(value, headers))); },
+            "body" => { result.extend(try!(check_body(value, body, false))); },
+            "body_matches" => { result.extend(try!(check_body(value, body, true))); },
+            "body_contains" => { result.extend(try!(check_body_contains(value, body))); },
+            _ => { panic!("BUG: unhandled expectation {}", key); },
+        }
+    }
+    Ok(result)
+}
+
+/// Format outcomes as a report, with one line per expectation
+pub fn format_report(outcomes: &Vec<Outcome>) -> String {
+    let failures = outcomes.iter().filter(|o| !o.passed).count();
+    let mut result = format!("Expectations: {} passed, {} failed\n",
+                             outcomes.len() - failures, failures);
+    for outcome in outcomes {
+        let label = if outcome.passed { "PASS" } else { "FAIL" };
+        result.push_str(&format!("    {} {}\n", label, outcome.message));
+    }
+    result.trim_right().to_string()
+}
+
+fn get_first_headers(resp: &http::Response) -> Vec<(String, String)> {
+    resp.get_headers().iter()
+        .filter(|&(_, v)| !v.is_empty())
+        .map(|(k, v)| (k.to_string(), v[0].to_string()))
+        .collect()
+}
+
+/// The expected status may be a code like 201, a class like "2xx", a range like "200-299", or a
+/// list of any of those.
+fn status_matches(expected: &Yaml, status: u32) -> Result<bool, String> {
+    let invalid = || format!("Invalid expected status {:?}", yaml_util::show_yaml(expected));
+    match *expected {
+        Yaml::Integer(i) => Ok(i == status as i64),
+        Yaml::String(ref s) => {
+            let s = s.trim().to_lowercase();
+            if s.len() == 3 && s.ends_with("xx") {
+                let class = try!(s[..1].parse::<u32>().map_err(|_| invalid()));
+                Ok(status / 100 == class)
+            } else if let Some(i) = s.find('-') {
+                let low = try!(s[..i].trim().parse::<u32>().map_err(|_| invalid()));
+                let high = try!(s[i + 1..].trim().parse::<u32>().map_err(|_| invalid()));
+                Ok(low <= status && status <= high)
+            } else {
+                let code = try!(s.parse::<u32>().map_err(|_| invalid()));
+                Ok(code == status)
+            }
+        },
+        Yaml::Array(ref arr) => {
+            for item in arr {
+                if try!(status_matches(item, status)) {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        },
+        _ => Err(invalid()),
+    }
+}
+
+fn expected_status_to_string(expected: &Yaml) -> String {
+    match *expected {
+        Yaml::Array(ref arr) => {
+            let items: Vec<String> = arr.iter().map(expected_status_to_string).collect();
+            items.join(" or ")
+        },
+        _ => yaml_util::show_yaml(expected),
+    }
+}
+
+fn check_status(expected: &Yaml, status: u32) -> Result<Outcome, String> {
+    let passed = try!(status_matches(expected, status));
+    let expected = expected_status_to_string(expected);
+    if passed {
+        Ok(Outcome::new(true, format!("status is {}", expected)))
+    } else {
+        Ok(Outcome::new(false, format!("status is {} (got {})", expected, status)))
+    }
+}
+
+/// Header names are case-insensitive. A header with no expected value only needs to be present.
+fn check_headers(expected: &Yaml, headers: &Vec<(String, String)>) -> Result<Vec<Outcome>, String> {
+    let mut result: Vec<Outcome> = Vec::new();
+    let expected = match *expected {
+        Yaml::Hash(ref h) => h,
+        _ => { return Err("Invalid expected headers. Expected a map.".to_string()); },
+    };
+    for (k, v) in expected.iter() {
+        let name = try!(yaml_util::scalar_to_string(k)
+            .ok_or(format!("Invalid expected header name {:?}", yaml_util::show_yaml(k))));
+        let actual = headers.iter()
+            .find(|&&(ref key, _)| key.to_lowercase() == name.to_lowercase())
+            .map(|&(_, ref val)| val.to_string());
+        let outcome =
+            match (actual, v) {
+                (None, _) => Outcome::new(false, format!("header {:?} is missing", name)),
+                (Some(_), &Yaml::Null) => Outcome::new(true, format!("header {:?} is present", name)),
+                (Some(actual), v) => {
+                    let value = try!(yaml_util::scalar_to_string(v)
+                        .ok_or(format!("Invalid expected value for header {:?}", name)));
+                    if actual == value {
+                        Outcome::new(true, format!("header {:?} is {:?}", name, value))
+                    } else {
+                        Outcome::new(false, format!("header {:?} is {:?} (got {:?})", name, value, actual))
+                    }
+                },
+            };
+        result.push(outcome);
+    }
+    Ok(result)
+}
+
+/// Each key is a key path into the json body, like "things.0.id". The value found at the key path
+/// must either equal the expected value, or match it as a regular expression.
+fn check_body(expected: &Yaml, body: &str, use_regex: bool) -> Result<Vec<Outcome>, String> {
+    let mut result: Vec<Outcome> = Vec::new();
+    let expected = match *expected {
+        Yaml::Hash(ref h) => h,
+        _ => { return Err("Invalid expected body. Expected a map of key paths.".to_string()); },
+    };
+    let json = Json::from_str(body);
+    for (k, v) in expected.iter() {
+        let key = try!(yaml_util::scalar_to_string(k)
+            .ok_or(format!("Invalid expected body key path {:?}", yaml_util::show_yaml(k))));
+        let value = try!(yaml_util::scalar_to_string(v)
+            .ok_or(format!("Invalid expected value for body key path {:?}", key)));
+        let key_path = remember::split_key_path(&key);
+        let verb = if use_regex { "matches" } else { "is" };
+
+        let actual = match json {
+            Ok(ref data) => remember::json_find_path(data, &key_path).and_then(|j| remember::json_to_string(&j)),
+            Err(_) => Err("response body is not json".to_string()),
+        };
+        let outcome =
+            match actual {
+                Ok(actual) => {
+                    let passed =
+                        if use_regex {
+                            let re = try!(Regex::new(&value)
+                                .map_err(|e| format!("Invalid regex {:?} -- {}", value, e)));
+                            re.is_match(&actual)
+                        } else {
+                            actual == value
+                        };
+                    if passed {
+                        Outcome::new(true, format!("body {} {} {:?}", key, verb, value))
+                    } else {
+                        Outcome::new(false, format!("body {} {} {:?} (got {:?})", key, verb, value, actual))
+                    }
+                },
+                Err(msg) => Outcome::new(false, format!("body {} {} {:?} ({})", key, verb, value, msg)),
+            };
+        result.push(outcome);
+    }
+    Ok(result)
+}
+
+fn check_body_contains(expected: &Yaml, body: &str) -> Result<Vec<Outcome>, String> {
+    let expected: Vec<&Yaml> =
+        if let &Yaml::Array(ref arr) = expected {
+            arr.iter().collect()
+        } else {
+            vec![expected]
+        };
+    let mut result: Vec<Outcome> = Vec::new();
+    for item in expected {
+        let text = try!(yaml_util::scalar_to_string(item)
+            .ok_or(format!("Invalid expected body text {:?}", yaml_util::show_yaml(item))));
+        if body.contains(&text) {
+            result.push(Outcome::new(true, format!("body contains {:?}", text)));
+        } else {
+            result.push(Outcome::new(false, format!("body contains {:?} (not found)", text)));
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,56 @@
+use curl::http;
+
+use super::env;
+use super::remember;
+use super::request::SpagRequest;
+
+/// Convert where to find a value in the response into a key path for remember::find_key()
+///     "body.things.0.id"      -> ["response", "body", "things", "0", "id"]
+///     "headers.X-Auth-Token"  -> ["response", "headers", "x-auth-token"]
+///     "status"                -> ["response", "status"]
+pub fn source_to_key_path(source: &str) -> Result<Vec<String>, String> {
+    let parts = remember::split_key_path(source);
+    let mut key_path = vec!["response".to_string()];
+    match (parts.first().cloned().unwrap_or(""), parts.len()) {
+        ("body", n) if n > 1 => {
+            key_path.extend(parts.iter().map(|p| p.to_string()));
+        },
+        // response headers are saved with lowercased names
+        ("headers", 2) => {
+            key_path.push("headers".to_string());
+            key_path.push(parts[1].to_lowercase());
+        },
+        ("status", 1) => {
+            key_path.push("status".to_string());
+        },
+        _ => {
+            return Err(format!(
+                "Invalid response value {:?}. Expected body.<key path>, headers.<name> or status", source));
+        },
+    }
+    Ok(key_path)
+}
+
+/// Save values from the response into the named environment, or the active one if the name is
+/// empty. Each extraction is a pair like ("headers.X-Auth-Token", "body.token") of the environment
+/// key to set and where in the response to find the value. Nothing is saved unless the response
+/// was successful (2xx).
+pub fn extract(req: &SpagRequest, resp: &http::Response, extractions: &Vec<(String, String)>,
+               env_name: &str) -> Result<(), String> {
+    let status = resp.get_code();
+    if extractions.is_empty() || status < 200 || status >= 300 {
+        return Ok(());
+    }
+    let y = try!(remember::serialize(req, resp));
+    let mut keys: Vec<String> = Vec::new();
+    let mut vals: Vec<String> = Vec::new();
+    for &(ref key, ref source) in extractions.iter() {
+        let key_path = try!(source_to_key_path(source));
+        let key_path: Vec<&str> = key_path.iter().map(|k| k.as_str()).collect();
+        let value = try!(remember::find_key(&y, &key_path, false)
+            .map_err(|e| format!("Failed to extract {} from the response -- {}", source, e)));
+        keys.push(key.to_string());
+        vals.push(value);
+    }
+    env::set_in_environment(env_name, &keys, &vals)
+}
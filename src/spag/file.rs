@@ -1,5 +1,6 @@
 extern crate yaml_rust;
 
+use std::env;
 use std::io::prelude::*;
 use std::fs;
 use std::fs::File;
@@ -7,6 +8,51 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::io::BufReader;
 
+/// The name of the directory spag keeps environments, history and remembered requests in
+pub const SPAG_DIR_NAME: &'static str = ".spag";
+
+/// Find the spag directory to use. SPAG_DIR overrides everything. Otherwise, this looks for a
+/// .spag directory in the current directory and then each of its parents, the way git finds .git.
+/// If there isn't one, .spag in the current directory is used, and is created when needed.
+pub fn get_spag_dir() -> String {
+    if let Ok(dir) = env::var("SPAG_DIR") {
+        if !dir.is_empty() {
+            return dir;
+        }
+    }
+    if Path::new(SPAG_DIR_NAME).is_dir() {
+        return SPAG_DIR_NAME.to_string();
+    }
+    if let Ok(current_dir) = env::current_dir() {
+        let mut dir = current_dir.parent();
+        while let Some(d) = dir {
+            let candidate = d.join(SPAG_DIR_NAME);
+            if candidate.is_dir() {
+                return candidate.to_str().unwrap().to_string();
+            }
+            dir = d.parent();
+        }
+    }
+    SPAG_DIR_NAME.to_string()
+}
+
+/// Returns the path to something in the spag directory, like spag_path("history.yml")
+pub fn spag_path(name: &str) -> String {
+    Path::new(&get_spag_dir()).join(name).to_str().unwrap().to_string()
+}
+
+/// Resolve a path from an environment against the project directory, the one holding the spag
+/// directory, so that it means the same thing from any of the project's subdirectories
+pub fn project_path(path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    match Path::new(&get_spag_dir()).parent() {
+        Some(root) => root.join(path).to_str().unwrap().to_string(),
+        None => path.to_string(),
+    }
+}
+
 pub fn read_file(filename: &str) -> Result<String, String> {
     if !Path::new(filename).exists() {
         return Err(format!("File {} does not exist", filename));
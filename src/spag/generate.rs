@@ -0,0 +1,94 @@
+use rand::{self, Rng};
+use time;
+
+/// The functions that can be called in a template, like {{ uuid() }}, and how many arguments
+/// each one takes as (min, max)
+const FUNCTIONS: [(&'static str, usize, usize); 5] = [
+    ("uuid", 0, 0),
+    ("now", 0, 1),
+    ("unix_time", 0, 0),
+    ("random_int", 2, 2),
+    ("random_string", 1, 1),
+];
+
+/// The format used by now() when no format is given
+const DEFAULT_TIME_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Check the function exists and is given the right number of arguments
+pub fn check(name: &str, args: &Vec<&str>) -> Result<(), String> {
+    match FUNCTIONS.iter().find(|&&(n, _, _)| n == name) {
+        Some(&(_, min, max)) => {
+            if args.len() < min || args.len() > max {
+                let expected =
+                    if min == max { format!("{}", min) } else { format!("{} to {}", min, max) };
+                Err(format!("Function {}() takes {} arguments, but {} were given",
+                            name, expected, args.len()))
+            } else {
+                Ok(())
+            }
+        },
+        None => {
+            let names: Vec<String> = FUNCTIONS.iter().map(|&(n, _, _)| format!("{}()", n)).collect();
+            Err(format!("Unknown template function {}(). Expected one of {}", name, names.join(", ")))
+        },
+    }
+}
+
+/// Call a function with its arguments. String arguments should already have their quotes removed.
+pub fn call(name: &str, args: &Vec<&str>) -> Result<String, String> {
+    try!(check(name, args));
+    match name {
+        "uuid" => Ok(uuid4()),
+        "now" => {
+            let fmt = if args.is_empty() { DEFAULT_TIME_FORMAT } else { args[0] };
+            let tm = time::now_utc();
+            let result = try!(tm.strftime(fmt)
+                .map_err(|e| format!("Invalid time format {:?} -- {}", fmt, e)));
+            Ok(format!("{}", result))
+        },
+        "unix_time" => Ok(format!("{}", time::get_time().sec)),
+        "random_int" => {
+            let low = try!(parse_int(name, args[0]));
+            let high = try!(parse_int(name, args[1]));
+            if low > high {
+                return Err(format!("Invalid range for random_int({}, {})", low, high));
+            }
+            Ok(format!("{}", random_int(low, high)))
+        },
+        "random_string" => {
+            let len = try!(parse_int(name, args[0]));
+            if len < 0 {
+                return Err(format!("Invalid length for random_string({})", len));
+            }
+            Ok(rand::thread_rng().gen_ascii_chars().take(len as usize).collect())
+        },
+        _ => Err(format!("BUG: no implementation for function {}()", name)),
+    }
+}
+
+/// Pick a random integer in an inclusive range, like random_int(1, 6) for a die. gen_range()
+/// excludes the high end, which can't be raised past i64::MAX, so shift the range down instead.
+fn random_int(low: i64, high: i64) -> i64 {
+    let mut rng = rand::thread_rng();
+    match high.checked_add(1) {
+        Some(end) => rng.gen_range(low, end),
+        None if low == i64::min_value() => rng.gen(),
+        None => rng.gen_range(low - 1, high) + 1,
+    }
+}
+
+fn parse_int(name: &str, arg: &str) -> Result<i64, String> {
+    arg.trim().parse::<i64>()
+        .map_err(|_| format!("Expected an integer argument to {}(), but found {:?}", name, arg))
+}
+
+/// Generate a random (version 4) uuid, like "0c8b3a0e-4f8e-4f5e-9b6a-3d2b1c0a9e8f"
+pub fn uuid4() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}",
+            hex[0..4].concat(), hex[4..6].concat(), hex[6..8].concat(),
+            hex[8..10].concat(), hex[10..16].concat())
+}
@@ -6,24 +6,32 @@ use yaml_rust::Yaml;
 
 use super::file;
 use super::yaml_util;
+use super::request;
 use super::request::SpagRequest;
 use super::remember;
+use super::secrets::Secrets;
 
-const HISTORY_DIR: &'static str = ".spag";
-const HISTORY_FILE: &'static str = ".spag/history.yml";
 const HISTORY_LIMIT: usize = 100;
 
+fn history_file() -> String {
+    file::spag_path("history.yml")
+}
+
 pub fn ensure_history_exists() {
-    if !Path::new(HISTORY_FILE).exists() {
-        file::ensure_dir_exists(HISTORY_DIR);
-        file::write_file(HISTORY_FILE, "[]");
+    let history_file = history_file();
+    if !Path::new(&history_file).exists() {
+        file::ensure_dir_exists(&file::get_spag_dir());
+        file::write_file(&history_file, "[]");
     }
 }
 
-pub fn append(req: &SpagRequest, resp: &http::Response) -> Result<(), String> {
+pub fn append(req: &SpagRequest, resp: &http::Response, secrets: &Secrets) -> Result<(), String> {
     ensure_history_exists();
 
-    let mut y = &mut try!(yaml_util::load_yaml_file(&HISTORY_FILE));
+    // redact first, so nothing is written if redacting fails
+    let new_entry = try!(remember::serialize_redacted(req, resp, secrets));
+
+    let mut y = &mut try!(yaml_util::load_yaml_file(&history_file()));
 
     if let Yaml::Array(ref mut arr) = *y {
 
@@ -34,12 +42,10 @@ pub fn append(req: &SpagRequest, resp: &http::Response) -> Result<(), String> {
             }
         }
 
-        let new_entry = remember::serialize(req, resp);
-
         arr.insert(0, new_entry);
     }
 
-    Ok(try!(yaml_util::dump_yaml_file(HISTORY_FILE, &y)))
+    Ok(try!(yaml_util::dump_yaml_file(&history_file(), &y)))
 }
 
 pub fn list() -> Result<Vec<String>, String> {
@@ -47,7 +53,7 @@ pub fn list() -> Result<Vec<String>, String> {
 
     let mut result = Vec::new();
 
-    let mut y = &mut try!(yaml_util::load_yaml_file(&HISTORY_FILE));
+    let mut y = &mut try!(yaml_util::load_yaml_file(&history_file()));
 
     if let Yaml::Array(ref mut arr) = *y {
         for y in arr.iter() {
@@ -67,7 +73,7 @@ pub fn get(raw_index: &String) -> Result<String, String> {
 
     let index = raw_index.parse().unwrap();
 
-    let mut y = &mut try!(yaml_util::load_yaml_file(&HISTORY_FILE));
+    let mut y = &mut try!(yaml_util::load_yaml_file(&history_file()));
 
     if let Yaml::Array(ref mut arr) = *y {
         let target = match arr.get(index) {
@@ -116,6 +122,40 @@ pub fn get(raw_index: &String) -> Result<String, String> {
 
         Ok(output.to_string())
     } else {
-        Err(format!("Failed to load history file {}", HISTORY_FILE))
+        Err(format!("Failed to load history file {}", history_file()))
     }
 }
+
+/// Rebuild the request at the given index in the history
+pub fn get_request(raw_index: &String) -> Result<SpagRequest, String> {
+    ensure_history_exists();
+
+    let index: usize = try!(raw_index.parse().map_err(|_| format!("Invalid index {:?}", raw_index)));
+
+    let y = try!(yaml_util::load_yaml_file(&history_file()));
+    let target = match y.as_vec().and_then(|arr| arr.get(index)) {
+        Some(yaml) => yaml,
+        None => return Err(format!("No request at #{}", index)),
+    };
+
+    let method = try!(yaml_util::get_value_as_string(&target, &["request", "method"]));
+    let endpoint = try!(yaml_util::get_value_as_string(&target, &["request", "endpoint"]));
+    let uri = try!(yaml_util::get_value_as_string(&target, &["request", "uri"]));
+    let body = try!(yaml_util::get_value_as_string(&target, &["request", "body"]));
+
+    let method = try!(request::method_from_str(&method));
+    let mut req = SpagRequest::new(method, endpoint, uri);
+    req.set_body(body);
+    match yaml_util::get_nested_value(&target, &["request", "headers"]) {
+        Some(&Yaml::Hash(ref headers)) => {
+            for (key, value) in headers.iter() {
+                if let (Some(k), Some(v)) = (key.as_str(), value.as_str()) {
+                    req.headers.insert(k.to_string(), v.to_string());
+                }
+            }
+        },
+        None => {},
+        _ => { return Err(format!("Invalid headers in request history #{}.", index))},
+    };
+    Ok(req)
+}
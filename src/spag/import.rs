@@ -0,0 +1,377 @@
+use std::str;
+use std::collections::VecDeque;
+
+use rustc_serialize::base64::{ToBase64, STANDARD};
+
+use super::file;
+use super::request;
+use super::template;
+
+/// curl options that take no value, and don't change the request. These are skipped.
+const IGNORED_CURL_FLAGS: [&'static str; 15] = [
+    "--compressed", "-s", "--silent", "-S", "--show-error", "-k", "--insecure", "-L",
+    "--location", "-v", "--verbose", "-i", "--include", "-g", "--globoff",
+];
+
+/// curl options that we support, and that take a value
+const CURL_OPTIONS_WITH_VALUES: [&'static str; 14] = [
+    "-X", "--request", "-H", "--header", "-d", "--data", "--data-ascii", "--data-binary",
+    "--data-raw", "-u", "--user", "-b", "--cookie", "--url",
+];
+
+/// A request parsed out of a curl command line
+#[derive(Debug, PartialEq)]
+pub struct CurlRequest {
+    pub method: String,
+    pub endpoint: String,
+    pub uri: String,
+    pub params: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// Split a command line into words, the way a POSIX shell would. This handles single quotes,
+/// double quotes, $'...' quotes, backslash escapes, and backslash-newline line continuations.
+pub fn shell_split(text: &str) -> Result<Vec<String>, String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = text.chars().peekable();
+    loop {
+        let c = match chars.next() {
+            Some(c) => c,
+            None => { break; },
+        };
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => { break; },
+                        Some(c) => { word.push(c); },
+                        None => { return Err("Unclosed single quote in command".to_string()); },
+                    }
+                }
+            },
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => { break; },
+                        Some('\\') => {
+                            match chars.next() {
+                                Some(c) if "\"\\$`".contains(c) => { word.push(c); },
+                                Some('\n') => {},
+                                Some(c) => { word.push('\\'); word.push(c); },
+                                None => { return Err("Unclosed double quote in command".to_string()); },
+                            }
+                        },
+                        Some(c) => { word.push(c); },
+                        None => { return Err("Unclosed double quote in command".to_string()); },
+                    }
+                }
+            },
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => { break; },
+                        Some('\\') => {
+                            match chars.next() {
+                                Some('n') => { word.push('\n'); },
+                                Some('t') => { word.push('\t'); },
+                                Some('r') => { word.push('\r'); },
+                                Some(c) => { word.push(c); },
+                                None => { return Err("Unclosed $' quote in command".to_string()); },
+                            }
+                        },
+                        Some(c) => { word.push(c); },
+                        None => { return Err("Unclosed $' quote in command".to_string()); },
+                    }
+                }
+            },
+            '\\' => {
+                match chars.next() {
+                    // a line continuation
+                    Some('\n') => {},
+                    Some(c) => { in_word = true; word.push(c); },
+                    None => { return Err("Trailing backslash in command".to_string()); },
+                }
+            },
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(word);
+                    word = String::new();
+                    in_word = false;
+                }
+            },
+            c => { in_word = true; word.push(c); },
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Decode a percent-encoded string from a url query, where '+' is a space
+pub fn url_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut result: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => { result.push(b' '); },
+            b'%' if i + 2 < bytes.len() && is_hex_digit(bytes[i + 1]) && is_hex_digit(bytes[i + 2]) => {
+                let hex = str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+                result.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 2;
+            },
+            b => { result.push(b); },
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+fn is_hex_digit(b: u8) -> bool {
+    (b as char).is_digit(16)
+}
+
+/// Split "http://localhost:5000/things?a=b" into ("http://localhost:5000", "/things", [("a", "b")])
+pub fn split_url(url: &str) -> Result<(String, String, Vec<(String, String)>), String> {
+    let scheme_end = match url.find("://") {
+        Some(i) => i + 3,
+        None => { return Err(format!("Invalid url {:?}. Expected something like http://host/path", url)); },
+    };
+    // drop the fragment. it never gets sent to the server anyway.
+    let url = url.split('#').next().unwrap();
+    let rest = &url[scheme_end..];
+    let path_start = rest.find(|c: char| c == '/' || c == '?').map(|i| i + scheme_end).unwrap_or(url.len());
+    let endpoint = url[..path_start].to_string();
+    let (path, query) =
+        match url[path_start..].find('?') {
+            Some(i) => (&url[path_start..path_start + i], &url[path_start + i + 1..]),
+            None => (&url[path_start..], ""),
+        };
+    let uri = if path.is_empty() { "/".to_string() } else { path.to_string() };
+    let params: Vec<(String, String)> = query.split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.find('=') {
+            Some(i) => (url_decode(&p[..i]), url_decode(&p[i + 1..])),
+            None => (url_decode(p), String::new()),
+        })
+        .collect();
+    Ok((endpoint, uri, params))
+}
+
+/// Parse a curl command, like the ones copied from a browser's developer tools.
+/// Supports -X, -H, -d/--data/--data-raw/--data-binary, -u, -b, -I and the url.
+pub fn parse_curl_command(command: &str) -> Result<CurlRequest, String> {
+    let mut words: VecDeque<String> = try!(shell_split(command)).into_iter().collect();
+    match words.pop_front() {
+        Some(ref w) if w == "curl" => {},
+        _ => { return Err("Expected a command starting with 'curl'".to_string()); },
+    }
+
+    let mut method = String::new();
+    let mut url = String::new();
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut data: Vec<String> = Vec::new();
+    loop {
+        let word = match words.pop_front() {
+            Some(w) => w,
+            None => { break; },
+        };
+        if !word.starts_with('-') {
+            if !url.is_empty() {
+                return Err(format!("Found more than one url: {:?} and {:?}", url, word));
+            }
+            url = word;
+            continue;
+        }
+        if IGNORED_CURL_FLAGS.iter().any(|&f| f == word) {
+            continue;
+        }
+        if word == "-I" || word == "--head" {
+            method = "HEAD".to_string();
+            continue;
+        }
+
+        // short flags may be grouped, like -sSL. split them up, and put them back to be read
+        // one at a time.
+        let short: String = word.chars().take(2).collect();
+        if !word.starts_with("--") && word.len() > 2 && !takes_value(&short) {
+            let mut split: Vec<String> = Vec::new();
+            for (i, c) in word.char_indices().skip(1) {
+                let flag = format!("-{}", c);
+                if takes_value(&flag) {
+                    // the rest of the group is this option's value, like -sXPOST
+                    split.push(format!("{}{}", flag, &word[i + c.len_utf8()..]));
+                    break;
+                }
+                split.push(flag);
+            }
+            for w in split.into_iter().rev() {
+                words.push_front(w);
+            }
+            continue;
+        }
+
+        // options may have their value attached, like -XPOST or --request=POST
+        let (option, attached) =
+            if word.starts_with("--") {
+                match word.find('=') {
+                    Some(i) => (word[..i].to_string(), Some(word[i + 1..].to_string())),
+                    None => (word.clone(), None),
+                }
+            } else if word.len() > 2 {
+                (short.clone(), Some(word[short.len()..].to_string()))
+            } else {
+                (word.clone(), None)
+            };
+        if !takes_value(&option) {
+            return Err(format!("Unsupported curl option {}", option));
+        }
+        let value = match attached.or_else(|| words.pop_front()) {
+            Some(v) => v,
+            None => { return Err(format!("Missing value for curl option {}", option)); },
+        };
+        match option.as_str() {
+            "-X" | "--request" => { method = value.to_uppercase(); },
+            "-H" | "--header" => {
+                let (k, v) = try!(request::split_header(&value));
+                headers.push((k.to_string(), v.to_string()));
+            },
+            "-d" | "--data" | "--data-ascii" | "--data-binary" => {
+                data.push(try!(read_data(&option, &value)));
+            },
+            "--data-raw" => { data.push(value); },
+            "-u" | "--user" => {
+                let credentials = value.as_bytes().to_base64(STANDARD);
+                headers.push(("Authorization".to_string(), format!("Basic {}", credentials)));
+            },
+            "-b" | "--cookie" => { headers.push(("Cookie".to_string(), value)); },
+            "--url" => { url = value; },
+            _ => { return Err(format!("Unsupported curl option {}", option)); },
+        }
+    }
+
+    if url.is_empty() {
+        return Err("No url found in curl command".to_string());
+    }
+    let (endpoint, uri, params) = try!(split_url(&url));
+    // like curl, send a POST if there's data and no method was given
+    if method.is_empty() {
+        method = if data.is_empty() { "GET".to_string() } else { "POST".to_string() };
+    }
+    Ok(CurlRequest {
+        method: method,
+        endpoint: endpoint,
+        uri: uri,
+        params: params,
+        headers: headers,
+        body: data.join("&"),
+    })
+}
+
+/// Whether a supported curl option needs a value
+fn takes_value(option: &str) -> bool {
+    CURL_OPTIONS_WITH_VALUES.iter().any(|&o| o == option)
+}
+
+/// Get the data for a -d option. Like curl, "@file" reads the data from a file, and for
+/// everything but --data-binary, newlines in the file are dropped.
+fn read_data(option: &str, value: &str) -> Result<String, String> {
+    if !value.starts_with('@') {
+        return Ok(value.to_string());
+    }
+    let filename = &value[1..];
+    if filename == "-" {
+        return Err(format!("Reading data from stdin ({} @-) is not supported", option));
+    }
+    let contents = try!(file::read_file(filename)
+        .map_err(|e| format!("Failed to read data for {} @{}: {}", option, filename, e)));
+    if option == "--data-binary" {
+        Ok(contents)
+    } else {
+        Ok(contents.replace("\r", "").replace("\n", ""))
+    }
+}
+
+/// Quote a string so yaml reads it back exactly
+fn yaml_quote(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => { result.push_str("\\\""); },
+            '\\' => { result.push_str("\\\\"); },
+            '\n' => { result.push_str("\\n"); },
+            '\t' => { result.push_str("\\t"); },
+            '\r' => { result.push_str("\\r"); },
+            c => { result.push(c); },
+        }
+    }
+    result.push('"');
+    result
+}
+
+impl CurlRequest {
+
+    /// Render this as the contents of a request file
+    pub fn to_request_yaml(&self) -> String {
+        let mut result = format!("method: {}\nuri: {}\n", self.method, yaml_quote(&self.uri));
+        if !self.headers.is_empty() {
+            result.push_str("headers:\n");
+            for &(ref k, ref v) in self.headers.iter() {
+                result.push_str(&format!("    {}: {}\n", yaml_quote(k), yaml_quote(v)));
+            }
+        }
+        if !self.params.is_empty() {
+            result.push_str("params:\n");
+            let mut keys: Vec<&String> = Vec::new();
+            for &(ref k, _) in self.params.iter() {
+                if !keys.contains(&k) {
+                    keys.push(k);
+                }
+            }
+            // repeated keys become a list of values
+            for key in keys {
+                let values: Vec<&String> = self.params.iter()
+                    .filter(|&&(ref k, _)| k == key)
+                    .map(|&(_, ref v)| v)
+                    .collect();
+                if values.len() == 1 {
+                    result.push_str(&format!("    {}: {}\n", yaml_quote(key), yaml_quote(values[0])));
+                } else {
+                    result.push_str(&format!("    {}:\n", yaml_quote(key)));
+                    for v in values {
+                        result.push_str(&format!("        - {}\n", yaml_quote(v)));
+                    }
+                }
+            }
+        }
+        if !self.body.is_empty() {
+            // a block scalar is easier to read and edit, but can't start with whitespace, and can
+            // keep at most one trailing newline without getting confusing
+            if self.body.starts_with(char::is_whitespace) || self.body.ends_with("\n\n") {
+                result.push_str(&format!("body: {}\n", yaml_quote(&self.body)));
+            } else {
+                let (indicator, body) =
+                    if self.body.ends_with('\n') {
+                        ("|", &self.body[..self.body.len() - 1])
+                    } else {
+                        ("|-", self.body.as_str())
+                    };
+                result.push_str(&format!("body: {}\n", indicator));
+                for line in body.split('\n') {
+                    result.push_str(&format!("    {}\n", line));
+                }
+            }
+        }
+        // request files are untemplated before they're parsed, so the values are sent as is
+        let use_shortcuts = false;
+        template::escape(&result, use_shortcuts)
+    }
+}
@@ -0,0 +1,119 @@
+use yaml_rust::{Yaml, YamlLoader};
+
+use super::request;
+use super::template;
+
+/// The keys every request file must have
+const REQUIRED_KEYS: [&'static str; 2] = ["method", "uri"];
+
+/// The methods defined by the http specs. Others are allowed, but are likely typos.
+const STANDARD_METHODS: [&'static str; 9] =
+    ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+
+/// What was found in a request file. Problems make the request file unusable, while warnings are
+/// for things that are allowed but probably mistakes.
+#[derive(Debug, PartialEq)]
+pub struct Lint {
+    pub problems: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Check the text of a request file without sending it. Each message starts with the filename
+/// and the line when we know it, like:
+///
+///     templates/get_thing.yml:2: Expected a string for key "uri"
+pub fn lint_request_file(filename: &str, text: &str) -> Lint {
+    let mut problems: Vec<String> = Vec::new();
+    let mut warnings: Vec<String> = Vec::new();
+    // we don't know the parameter values, so parse the yaml with a stand-in for each one
+    let y = match load_stubbed(filename, text, "x") {
+        Ok(y) => y,
+        Err(e) => {
+            problems.push(e);
+            return Lint { problems: problems, warnings: warnings };
+        },
+    };
+
+    for key in REQUIRED_KEYS.iter() {
+        match y[*key] {
+            Yaml::String(_) => {},
+            Yaml::BadValue => {
+                problems.push(format!("{}: Missing required key {:?}", filename, key));
+            },
+            _ => {
+                let location = show_location(filename, text, key);
+                problems.push(format!("{}: Expected a string for key {:?}", location, key));
+            },
+        }
+    }
+
+    // a templated method can only be checked when the request is sent. the method is templated
+    // if it changes with a different stand-in
+    if let Some(method) = y["method"].as_str() {
+        let other = load_stubbed(filename, text, "y").ok();
+        let templated = other.map(|other| other["method"] != y["method"]).unwrap_or(true);
+        if !templated {
+            let location = show_location(filename, text, "method");
+            match request::method_from_str(method) {
+                Ok(ref m) if !STANDARD_METHODS.contains(&m.as_str()) => {
+                    warnings.push(format!("{}: Warning: {:?} isn't a standard http method",
+                                          location, method));
+                },
+                Ok(_) => {},
+                Err(e) => { problems.push(format!("{}: {}", location, e)); },
+            }
+        }
+    }
+    Lint { problems: problems, warnings: warnings }
+}
+
+/// Parse the request file as yaml, with each substitution replaced by the placeholder. The file
+/// must be a yaml mapping.
+fn load_stubbed(filename: &str, text: &str, placeholder: &str) -> Result<Yaml, String> {
+    let use_shortcuts = false;
+    let stubbed = try!(template::stub_templates(text, filename, placeholder, use_shortcuts));
+    let mut docs = try!(YamlLoader::load_from_str(&stubbed)
+        .map_err(|e| format!("{}: Invalid yaml -- {}", filename, e)));
+    if docs.is_empty() {
+        return Err(format!("{}: The request file is empty", filename));
+    }
+    let y = docs.remove(0);
+    if y.as_hash().is_none() {
+        return Err(format!("{}: Expected the request file to be a yaml mapping", filename));
+    }
+    Ok(y)
+}
+
+/// Show the filename with the line of a top-level key, if we can find it
+fn show_location(filename: &str, text: &str, key: &str) -> String {
+    match find_key_line(text, key) {
+        Some(line) => format!("{}:{}", filename, line),
+        None => filename.to_string(),
+    }
+}
+
+/// Find the line number of a top-level key in block style yaml text. The key may be quoted.
+fn find_key_line(text: &str, key: &str) -> Option<usize> {
+    text.lines()
+        .position(|line| top_level_key(line) == Some(key))
+        .map(|i| i + 1)
+}
+
+/// Get the key from a line like `key: value`, `"key": value` or `'key': value`
+fn top_level_key(line: &str) -> Option<&str> {
+    let (key, rest) = if line.starts_with("\"") || line.starts_with("'") {
+        let quote = &line[..1];
+        match line[1..].find(quote) {
+            Some(i) => (&line[1..i + 1], &line[i + 2..]),
+            None => { return None; },
+        }
+    } else if line.starts_with(|c: char| !c.is_whitespace() && c != '#' && c != '-') {
+        match line.find(':') {
+            Some(i) => (line[..i].trim_right(), &line[i..]),
+            None => { return None; },
+        }
+    } else {
+        return None;
+    };
+    if rest.trim_left().starts_with(":") { Some(key) } else { None }
+}
@@ -4,6 +4,8 @@ extern crate docopt;
 use std;
 use std::io::prelude::*;
 use std::collections::hash_map::HashMap;
+use std::fs::PathExt;
+use std::path::Path;
 use std::path::PathBuf;
 
 use curl::http;
@@ -11,37 +13,65 @@ use yaml_rust::Yaml;
 
 use super::args;
 use super::args::EnvArgs;
+use super::args::InitArgs;
 use super::args::MainArgs;
 use super::args::MethodArgs;
 use super::args::RequestArgs;
 use super::args::HistoryArgs;
+use super::args::ImportArgs;
+use super::args::RunArgs;
 
 use super::env;
+use super::expect;
+use super::extract;
 use super::file;
 use super::history;
+use super::import;
+use super::lint;
+use super::prompt;
 use super::remember;
 use super::request;
+use super::secrets;
+use super::secrets::Secrets;
 use super::request::SpagRequest;
+use super::suite;
 use super::template;
 use super::yaml_util;
 
 
 pub fn main() {
     let argv: Vec<String> = std::env::args().collect();
-    let main_argv: Vec<String> = std::env::args().take(2).collect();
+    let (argv, spag_dir) = try_error!(args::take_global_flag(&argv, "--spag-dir"));
+    // the flag wins over SPAG_DIR, and file::get_spag_dir() reads SPAG_DIR
+    if let Some(dir) = spag_dir {
+        std::env::set_var("SPAG_DIR", dir);
+    }
+    // the environment given with --env or SPAG_ENV is passed to whatever needs it
+    let (argv, flag_env) = try_error!(args::take_global_flag(&argv, "--env"));
+    let env_name = args::get_env_name(&flag_env);
+    let main_argv: Vec<String> = argv.iter().take(2).cloned().collect();
     let args: MainArgs = args::parse_main_args(&main_argv);
     match args.arg_command.as_str() {
+        "init" => {
+            spag_init(&args::parse_init_args(&argv))
+        },
         "env" => {
-            spag_env(&args::parse_env_args(&argv))
+            spag_env(&args::parse_env_args(&argv), &env_name)
         },
         "r" | "request" => {
-            spag_request(&args::parse_request_args(&argv))
+            spag_request(&args::parse_request_args(&argv), &env_name)
         },
         "history" => {
             spag_history(&args::parse_history_args(&argv))
         },
-        "get" | "post" | "put" | "patch" | "delete" => {
-            spag_method(&args::parse_method_args(&argv))
+        "run" => {
+            spag_run(&args::parse_run_args(&argv), &env_name)
+        },
+        "import" => {
+            spag_import(&args::parse_import_args(&argv), &env_name)
+        },
+        "get" | "post" | "put" | "patch" | "delete" | "head" | "options" | "send" => {
+            spag_method(&args::parse_method_args(&argv), &env_name)
         },
         command if command.is_empty() => {
             printerrln!("Received no command or options");
@@ -54,54 +84,93 @@ pub fn main() {
     }
 }
 
-fn spag_env(args: &EnvArgs) {
+fn spag_init(_: &InitArgs) {
+    let dir = match std::env::var("SPAG_DIR") {
+        Ok(ref dir) if !dir.is_empty() => dir.to_string(),
+        _ => file::SPAG_DIR_NAME.to_string(),
+    };
+    if Path::new(&dir).exists() {
+        error!("A spag directory already exists at {}", dir);
+    }
+    // creating the active environment creates the directory with it
+    std::env::set_var("SPAG_DIR", &dir);
+    env::get_active_environment_name();
+    println!("Created spag directory {}", dir);
+}
+
+fn spag_env(args: &EnvArgs, env_name: &str) {
+    // commands that take an optional environment use the selected one when it isn't given
+    let name =
+        if args.arg_environment.is_empty() { env_name } else { args.arg_environment.as_str() };
     if args.cmd_cat {
-        spag_env_cat(&args);
+        spag_env_cat(&args, name);
     } else if args.cmd_set {
-        spag_env_set(&args);
+        spag_env_set(&args, name);
     } else if args.cmd_unset {
-        spag_env_unset(&args);
+        spag_env_unset(&args, name);
     } else if args.cmd_activate {
         spag_env_activate(&args);
     } else if args.cmd_deactivate {
         spag_env_deactivate();
     } else if args.cmd_ls {
         spag_env_ls();
+    } else if args.cmd_copy {
+        spag_env_copy(&args);
+    } else if args.cmd_rename {
+        spag_env_rename(&args);
+    } else if args.cmd_rm {
+        spag_env_rm(&args, env_name);
+    } else if args.cmd_diff {
+        spag_env_diff(&args);
+    } else if args.cmd_import {
+        spag_env_import(&args, name);
+    } else if args.cmd_export {
+        spag_env_export(&args, name);
     } else {
         error!("BUG: Invalid command");
     }
 }
 
-fn spag_env_set(args: &EnvArgs) {
+fn spag_env_set(args: &EnvArgs, name: &str) {
     let use_shortcuts = true;
-    let withs: HashMap<String, String> = args::get_withs(&args.arg_key, &args.arg_val);
+    let withs: HashMap<String, String> = args::get_withs(&args.arg_key, &args.arg_val, name);
     let withs: HashMap<&str, &str> = withs.iter()
         .map(|(k, v)| (k.as_str(), v.as_str()))
         .collect();
 
-    // untemplate all of the values
-    let mut vals: Vec<String> = Vec::new();
-    for v in &args.arg_val {
-        let value = try_error!(template::untemplate(v.as_str(), &withs, use_shortcuts));
-        vals.push(value);
+    // untemplate all of the values, and then parse them as the given type
+    let mut vals: Vec<Yaml> = Vec::new();
+    for (k, v) in args.arg_key.iter().zip(args.arg_val.iter()) {
+        let value = try_error!(template::untemplate(v.as_str(), k.as_str(), &withs, name,
+                                                    use_shortcuts));
+        vals.push(try_error!(yaml_util::parse_typed_value(&value, &args.flag_type)));
     }
 
-    try_error!(env::set_in_environment(&args.arg_environment, &args.arg_key, &vals));
-    try_error!(env::show_environment(&args.arg_environment));
+    if args.flag_global {
+        try_error!(env::set_yaml_in_environment(name, env::Scope::Global, &args.arg_key, &vals));
+        try_error!(env::show_global_environment(name));
+    } else {
+        try_error!(env::set_yaml_in_environment(name, env::Scope::Project, &args.arg_key, &vals));
+        try_error!(env::show_environment(name));
+    }
 }
 
-fn spag_env_unset(args: &EnvArgs) {
+fn spag_env_unset(args: &EnvArgs, name: &str) {
     if !args.flag_everything {
-        try_error!(env::unset_in_environment(&args.arg_environment, &args.arg_key));
-        try_error!(env::show_environment(&args.arg_environment));
+        try_error!(env::unset_in_environment(name, &args.arg_key));
+        try_error!(env::show_environment(name));
     } else {
-        try_error!(env::unset_all_environment(&args.arg_environment));
-        try_error!(env::show_environment(&args.arg_environment));
+        try_error!(env::unset_all_environment(name));
+        try_error!(env::show_environment(name));
     }
 }
 
-fn spag_env_cat(args: &EnvArgs) {
-    try_error!(env::show_environment(&args.arg_environment));
+fn spag_env_cat(args: &EnvArgs, name: &str) {
+    if args.flag_resolved {
+        try_error!(env::show_resolved_environment(name));
+    } else {
+        try_error!(env::show_environment(name));
+    }
 }
 
 fn spag_env_activate(args: &EnvArgs) {
@@ -117,6 +186,33 @@ fn spag_env_ls() {
     try_error!(env::list_environments());
 }
 
+fn spag_env_copy(args: &EnvArgs) {
+    try_error!(env::copy_environment(&args.arg_environment, &args.arg_new_environment));
+    try_error!(env::show_environment(&args.arg_new_environment));
+}
+
+fn spag_env_rename(args: &EnvArgs) {
+    try_error!(env::rename_environment(&args.arg_environment, &args.arg_new_environment));
+    try_error!(env::show_environment(&args.arg_new_environment));
+}
+
+fn spag_env_rm(args: &EnvArgs, env_name: &str) {
+    try_error!(env::remove_environment(&args.arg_environment, env_name, args.flag_force));
+}
+
+fn spag_env_diff(args: &EnvArgs) {
+    try_error!(env::show_environment_diff(&args.arg_environment, &args.arg_other_environment));
+}
+
+fn spag_env_import(args: &EnvArgs, name: &str) {
+    try_error!(env::import_dotenv(name, &args.flag_dotenv));
+    try_error!(env::show_environment(name));
+}
+
+fn spag_env_export(args: &EnvArgs, name: &str) {
+    try_error!(env::export_environment(name, &args.flag_format, args.flag_show_secrets));
+}
+
 fn spag_history(args: &HistoryArgs) {
     if !args.arg_index.is_empty() {
         spag_history_show(&args);
@@ -131,129 +227,322 @@ fn spag_history(args: &HistoryArgs) {
 }
 
 fn spag_history_show(args: &HistoryArgs) {
-    let out = try_error!(history::get(&args.arg_index));
-    println!("{}", out);
+    if args.flag_as_curl {
+        let req = try_error!(history::get_request(&args.arg_index));
+        let curl = req.to_curl();
+        if curl.contains(secrets::REDACTED) {
+            printerrln!("Warning: this request contains redacted secrets ({}). Fill them in \
+                         before running the command.", secrets::REDACTED);
+        }
+        println!("{}", curl);
+    } else {
+        let out = try_error!(history::get(&args.arg_index));
+        println!("{}", out);
+    }
 }
 
-fn spag_request(args: &RequestArgs) {
+fn spag_request(args: &RequestArgs, env_name: &str) {
     if args.cmd_ls {
-        spag_request_ls(args);
+        spag_request_ls(args, env_name);
+    } else if args.cmd_lint {
+        spag_request_lint(args, env_name);
     } else if args.cmd_cat {
-        spag_request_cat(args);
+        spag_request_cat(args, env_name);
     } else if args.cmd_inspect {
-        spag_request_inspect(args);
+        spag_request_inspect(args, env_name);
     } else {
-        spag_request_a_file(args);
+        spag_request_a_file(args, env_name);
+    }
+}
+
+fn spag_request_a_file(args: &RequestArgs, env_name: &str) {
+    let endpoint = try_error!(args::get_endpoint(&args.flag_endpoint, env_name));
+    let dir = try_error!(args::get_dir(&args.flag_dir, env_name));
+    let mut withs: HashMap<String, String> =
+        args::get_withs(&args.arg_key, &args.arg_val, env_name);
+    if prompt::should_prompt(args.flag_prompt, args.flag_no_prompt) {
+        let answers = try_error!(ask_for_missing_params(&args.arg_file, &dir, &withs, env_name));
+        withs.extend(answers);
+    }
+    let withs: HashMap<&str, &str> = withs.iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+
+    let (req, y) = try_error!(build_request_from_file(&args.arg_file, &dir, endpoint, &withs,
+                                                      env_name, &args.flag_data, &args.flag_header,
+                                                      &args.flag_param));
+    if args.flag_as_curl {
+        println!("{}", req.to_curl());
+        return;
+    }
+    let extractions = try_error!(args::resolve_extractions(&args.flag_set_env, &y));
+    if args.flag_dry_run {
+        println!("{}", req.describe());
+        return;
     }
+    let expectations = yaml_util::get_nested_value(&y, &["expect"]);
+    do_request(&req, &args.flag_remember_as, args.flag_verbose, &extractions, expectations, env_name);
 }
 
-fn spag_request_a_file(args: &RequestArgs) {
-    let endpoint = try_error!(args::get_endpoint(&args.flag_endpoint));
-    let dir = try_error!(args::get_dir(args));
-    let withs: HashMap<String, String> = args::get_withs(&args.arg_key, &args.arg_val);
+/// Prompt for the parameters in the request file that weren't given with --with
+fn ask_for_missing_params(name: &str, dir: &str, withs: &HashMap<String, String>, env_name: &str
+                          ) -> Result<HashMap<String, String>, String> {
+    let request_filename = try!(request::get_request_filename(name, dir));
+    let contents = try!(file::read_file(&request_filename));
     let withs: HashMap<&str, &str> = withs.iter()
         .map(|(k, v)| (k.as_str(), v.as_str()))
         .collect();
+    let use_shortcuts = false;
+    prompt::ask_for_missing_params(&contents, &request_filename, &withs, env_name, use_shortcuts)
+}
 
+/// Load the request file, untemplate it, and build a SpagRequest from it. The data, header and
+/// param flags override or add to what's in the file. Returns the request along with the
+/// untemplated yaml of the request file.
+fn build_request_from_file(name: &str, dir: &str, endpoint: String, withs: &HashMap<&str, &str>,
+                           env_name: &str, flag_data: &str, flag_header: &Vec<String>,
+                           flag_param: &Vec<String>) -> Result<(SpagRequest, Yaml), String> {
     // load the request file, but untemplate it first
-    let request_filename = try_error!(request::get_request_filename(&args.arg_file, &dir));
-    let yaml_string = try_error!(file::read_file(&request_filename));
+    let request_filename = try!(request::get_request_filename(name, dir));
+    let yaml_string = try!(file::read_file(&request_filename));
     let use_shortcuts = false;
-    let yaml_string = try_error!(template::untemplate(&yaml_string, &withs, use_shortcuts));
-
-    match yaml_util::load_yaml_string(&yaml_string) {
-        Ok(y) => {
-            let method = try_error!(yaml_util::get_value_as_string(&y, &["method"]));
-            let uri = try_error!(yaml_util::get_value_as_string(&y, &["uri"]));
-
-            // the request body can be overridden by the --data flag.
-            //
-            // todo? because docopt defaults to an empty string if the data flag isn't given,
-            // we can't tell if the user is trying to override the body to be empty.
-            let data = try_error!(args::get_data(&args.flag_data, &withs));
-            let body =
-                if !data.is_empty() {
-                    data
-                } else {
-                    if let Some(&Yaml::String(ref b)) = yaml_util::get_nested_value(&y, &["body"]) {
-                        b.to_string()
-                    } else {
-                        String::new()
-                    }
-                };
-
-            let headers = try_error!(args::resolve_headers(&args.flag_header, &y));
-
-            let mut req = SpagRequest::new(request::method_from_str(&method), endpoint, uri);
-            try_error!(req.add_headers(headers.iter()));
-            req.set_body(body);
-            do_request(&req, &args.flag_remember_as, args.flag_verbose);
-        },
-        Err(msg) => { error!("{}", msg); }
-    }
+    let yaml_string = try!(template::untemplate_request_file(&yaml_string, &request_filename,
+                                                             withs, env_name, use_shortcuts));
+    let y = try!(yaml_util::load_yaml_string(&yaml_string));
+
+    let method = try!(yaml_util::get_value_as_string(&y, &["method"]));
+    let uri = try!(yaml_util::get_value_as_string(&y, &["uri"]));
+
+    // the request body can be overridden by the --data flag.
+    //
+    // todo? because docopt defaults to an empty string if the data flag isn't given,
+    // we can't tell if the user is trying to override the body to be empty.
+    let data = try!(args::get_data(flag_data, withs, env_name));
+    let body =
+        if !data.is_empty() {
+            data
+        } else {
+            if let Some(&Yaml::String(ref b)) = yaml_util::get_nested_value(&y, &["body"]) {
+                b.to_string()
+            } else {
+                String::new()
+            }
+        };
+
+    let headers = try!(args::resolve_headers(flag_header, &y, env_name));
+    let params = try!(args::resolve_params(flag_param, &y, env_name));
+
+    let method = try!(request::method_from_str(&method));
+    let mut req = SpagRequest::new(method, endpoint, uri);
+    try!(req.add_headers(headers.iter()));
+    req.add_params(params.into_iter());
+    req.set_body(body);
+    Ok((req, y))
 }
 
-fn spag_request_cat(args: &RequestArgs) {
-    let dir = try_error!(args::get_dir(args));
+fn spag_request_cat(args: &RequestArgs, env_name: &str) {
+    let dir = try_error!(args::get_dir(&args.flag_dir, env_name));
     let filename = try_error!(request::get_request_filename(&args.arg_file, &dir));
     let contents = try_error!(file::read_file(&filename));
     println!("{}", contents);
 }
 
-fn spag_request_inspect(args: &RequestArgs) {
-    let dir = try_error!(args::get_dir(args));
+fn spag_request_inspect(args: &RequestArgs, env_name: &str) {
+    let dir = try_error!(args::get_dir(&args.flag_dir, env_name));
     let filename = try_error!(request::get_request_filename(&args.arg_file, &dir));
     let contents = try_error!(file::read_file(&filename));
     let use_shortcuts = true;
-    let out = try_error!(template::show_params(&contents, use_shortcuts));
+    let out = try_error!(template::show_params(&contents, &filename, env_name, use_shortcuts));
     println!("{}", out);
 }
 
-fn spag_request_ls(args: &RequestArgs) {
-    let dir = try_error!(args::get_dir(args));
-    let filenames = try_error!(file::walk_dir(&dir));
+fn spag_request_ls(args: &RequestArgs, env_name: &str) {
+    let dir = try_error!(args::get_dir(&args.flag_dir, env_name));
+    for filename in try_error!(find_request_files(&dir)) {
+        println!("{}", filename);
+    }
+}
+
+fn spag_request_lint(args: &RequestArgs, env_name: &str) {
+    let dir = try_error!(args::get_dir(&args.flag_dir, env_name));
+    // warnings are shown, but only problems fail the lint
+    let mut problem_count = 0;
+    for filename in try_error!(find_request_files(&dir)) {
+        let found = match file::read_file(&filename) {
+            Ok(text) => lint::lint_request_file(&filename, &text),
+            Err(e) => {
+                lint::Lint { problems: vec![format!("{}: {}", filename, e)], warnings: Vec::new() }
+            },
+        };
+        for message in found.problems.iter().chain(found.warnings.iter()) {
+            printerrln!("{}", message);
+        }
+        problem_count += found.problems.len();
+    }
+    if problem_count > 0 {
+        error!("Found {} problem(s) in request files", problem_count);
+    }
+}
+
+/// Find the request files in the directory, sorted, relative to the current directory if they're
+/// underneath it
+fn find_request_files(dir: &str) -> Result<Vec<String>, String> {
+    let filenames = try!(file::walk_dir(dir));
     let mut yaml_files: Vec<&PathBuf> = filenames.iter()
         .filter(|p| p.to_str().unwrap().ends_with(".yml"))
         .collect();
     yaml_files.sort();
 
-    let current_dir = try_error!(std::env::current_dir());
+    let current_dir = try!(std::env::current_dir().map_err(|e| e.to_string()));
+    let mut result: Vec<String> = Vec::new();
     for file in yaml_files.iter() {
         if file.starts_with(&current_dir) {
             // relative_from() is unstable
-            println!("{}", file.relative_from(&current_dir).unwrap().to_str().unwrap());
+            result.push(file.relative_from(&current_dir).unwrap().to_str().unwrap().to_string());
         } else {
-            println!("{}", file.to_str().unwrap());
+            result.push(file.to_str().unwrap().to_string());
         }
     }
+    Ok(result)
 }
 
-fn spag_method(args: &MethodArgs) {
-    // untemplate the resource
+fn spag_method(args: &MethodArgs, env_name: &str) {
     let use_shortcuts = true;
+    // with --no-template, escape everything so it comes out of the templating unchanged
+    let escape = |s: &String| {
+        if args.flag_no_template { template::escape(s, use_shortcuts) } else { s.to_string() }
+    };
+    let flag_header: Vec<String> = args.flag_header.iter().map(&escape).collect();
+    let flag_param: Vec<String> = args.flag_param.iter().map(&escape).collect();
+
+    // untemplate the resource
     let withs: HashMap<&str, &str> = HashMap::new();
-    let resource = try_error!(template::untemplate(&args.arg_path, &withs, use_shortcuts));
+    let path = escape(&args.arg_path);
+    let resource = try_error!(template::untemplate(&path, "path", &withs, env_name,
+                                                   use_shortcuts));
 
-    let method = args::get_method_from_args(args);
-    let endpoint = try_error!(args::get_endpoint(&args.flag_endpoint));
+    let method = try_error!(args::get_method_from_args(args));
+    let endpoint = try_error!(args::get_endpoint(&args.flag_endpoint, env_name));
     let mut req = SpagRequest::new(method, endpoint, resource);
-    let headers = try_error!(args::resolve_headers_no_request_file(&args.flag_header));
+    let headers = try_error!(args::resolve_headers_no_request_file(&flag_header, env_name));
     try_error!(req.add_headers(headers.iter()));
+    let params = try_error!(args::resolve_params_no_request_file(&flag_param, env_name));
+    req.add_params(params.into_iter());
 
-    let body = try_error!(args::get_data(&args.flag_data, &withs));
+    let body = try_error!(args::get_data(&escape(&args.flag_data), &withs, env_name));
     req.set_body(body);
-    do_request(&req, &args.flag_remember_as, args.flag_verbose);
+    let extractions = try_error!(args::resolve_extractions_no_request_file(&args.flag_set_env));
+    if args.flag_dry_run {
+        println!("{}", req.describe());
+        return;
+    }
+    do_request(&req, &args.flag_remember_as, args.flag_verbose, &extractions, None, env_name);
 }
 
-fn do_request(req: &SpagRequest, remember_as: &str, verbose: bool) {
-    let mut handle = http::handle();
-    let resp = try_error!(req.prepare(&mut handle).exec());
+fn spag_import(args: &ImportArgs, env_name: &str) {
+    let dir = try_error!(args::get_dir(&args.flag_dir, env_name));
+    let curl_request = try_error!(import::parse_curl_command(&args.arg_command));
 
-    try_error!(history::append(req, &resp));
-    try_error!(remember::remember(req, &resp, "last.yml"));
-    if !remember_as.is_empty() {
-        try_error!(remember::remember(req, &resp, remember_as));
+    let path = Path::new(&dir).join(file::ensure_extension(&args.flag_save, "yml"));
+    if path.exists() && !args.flag_force {
+        error!("Request file {} already exists. Use --force to overwrite it.", path.to_str().unwrap());
+    }
+    file::ensure_dir_exists(path.parent().unwrap().to_str().unwrap());
+    file::write_file(path.to_str().unwrap(), &curl_request.to_request_yaml());
+    println!("Saved request file {}", path.to_str().unwrap());
+
+    // the request file only has the uri, so make sure the user knows where to send it
+    let endpoint = args::get_endpoint("", env_name).unwrap_or(String::new());
+    if endpoint != curl_request.endpoint {
+        println!("The url's endpoint differs from the environment's. To use it, run:");
+        println!("    spag env set endpoint {}", curl_request.endpoint);
+    }
+}
+
+fn spag_run(args: &RunArgs, env_name: &str) {
+    let steps = try_error!(suite::load_suite(&args.arg_suite));
+    let endpoint = try_error!(args::get_endpoint(&args.flag_endpoint, env_name));
+    let dir = try_error!(args::get_dir(&args.flag_dir, env_name));
+
+    let mut ran = 0;
+    let mut failures = 0;
+    for (i, step) in steps.iter().enumerate() {
+        ran += 1;
+        match run_step(step, &endpoint, &dir, env_name, args.flag_verbose) {
+            Ok(summary) => { println!("{}. {}: {}", i + 1, step.request, summary); },
+            Err(summary) => {
+                failures += 1;
+                println!("{}. {}: {}", i + 1, step.request, summary);
+                if !args.flag_continue_on_error {
+                    break;
+                }
+            },
+        }
+    }
+    println!("Ran {} of {} steps: {} passed, {} failed", ran, steps.len(), ran - failures, failures);
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Send the request for a single step in a suite, and return a summary of how it went.
+/// A step fails if any of its expectations fail. If there are no expectations, it fails on a
+/// status code of 400 or above.
+fn run_step(step: &suite::Step, endpoint: &str, dir: &str, env_name: &str, verbose: bool
+            ) -> Result<String, String> {
+    // untemplate the with values right before sending, so they can use earlier responses
+    let use_shortcuts = true;
+    let mut withs: HashMap<String, String> = HashMap::new();
+    for (k, v) in step.withs.iter() {
+        let source = format!("{} with {}", step.request, k);
+        let v = try!(template::untemplate(v, &source, &HashMap::new(), env_name, use_shortcuts)
+            .map_err(|e| format!("FAILED\n    {}", e)));
+        withs.insert(k.to_string(), v);
+    }
+    let withs: HashMap<&str, &str> = withs.iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+
+    let no_flags: Vec<String> = Vec::new();
+    let (req, y) = try!(build_request_from_file(&step.request, dir, endpoint.to_string(), &withs,
+                                                env_name, "", &no_flags, &no_flags)
+        .map_err(|e| format!("FAILED\n    {}", e)));
+    let extractions = try!(args::resolve_extractions(&no_flags, &y)
+        .map_err(|e| format!("FAILED\n    {}", e)));
+    let resp = try!(send_request(&req, &step.remember_as, env_name)
+        .map_err(|e| format!("FAILED\n    {}", e)));
+    try!(extract::extract(&req, &resp, &extractions, env_name)
+        .map_err(|e| format!("FAILED\n    {}", e)));
+
+    if verbose {
+        let output = std::str::from_utf8(resp.get_body()).unwrap();
+        println!("{}", yaml_util::pretty_json(output));
+    }
+
+    let status = resp.get_code();
+    let summary = format!("{} {}{} -> {}", req.get_method_string(), req.endpoint,
+                          req.get_uri_with_params(), status);
+    match yaml_util::get_nested_value(&y, &["expect"]) {
+        Some(expectations) => {
+            let outcomes = try!(expect::check(expectations, &resp)
+                .map_err(|e| format!("{} FAILED\n    {}", summary, e)));
+            let failures = outcomes.iter().filter(|o| !o.passed).count();
+            let report = expect::format_report(&outcomes).replace("\n", "\n    ");
+            if failures > 0 {
+                Err(format!("{} FAILED\n    {}", summary, report))
+            } else {
+                Ok(format!("{} ok\n    {}", summary, report))
+            }
+        },
+        None if status >= 400 => Err(format!("{} FAILED", summary)),
+        None => Ok(format!("{} ok", summary)),
     }
+}
+
+fn do_request(req: &SpagRequest, remember_as: &str, verbose: bool,
+              extractions: &Vec<(String, String)>, expectations: Option<&Yaml>, env_name: &str) {
+    let resp = try_error!(send_request(req, remember_as, env_name));
+    try_error!(extract::extract(req, &resp, extractions, env_name));
 
     if verbose {
         let out = try_error!(history::get(&"0".to_string()));
@@ -263,4 +552,28 @@ fn do_request(req: &SpagRequest, remember_as: &str, verbose: bool) {
         println!("{}", yaml_util::pretty_json(output));
     }
 
+    // the report goes to stderr so the response body on stdout can still be piped elsewhere
+    if let Some(expectations) = expectations {
+        let outcomes = try_error!(expect::check(expectations, &resp));
+        printerrln!("{}", expect::format_report(&outcomes));
+        let failures = outcomes.iter().filter(|o| !o.passed).count();
+        if failures > 0 {
+            error!("{} of {} expectations failed", failures, outcomes.len());
+        }
+    }
+}
+
+/// Send the request, then save it to the history and to the remembered requests, with the secrets
+/// in the environment redacted. The secrets are found first, so nothing is sent if they can't be.
+fn send_request(req: &SpagRequest, remember_as: &str, env_name: &str
+                ) -> Result<http::Response, String> {
+    let secrets = try!(Secrets::load(env_name));
+    let resp = try!(req.send());
+
+    try!(history::append(req, &resp, &secrets));
+    try!(remember::remember(req, &resp, "last.yml", &secrets));
+    if !remember_as.is_empty() {
+        try!(remember::remember(req, &resp, remember_as, &secrets));
+    }
+    Ok(resp)
 }
@@ -45,12 +45,22 @@ macro_rules! parse_args {
 }
 
 pub mod args;
+pub mod dotenv;
+pub mod easy;
 pub mod env;
+pub mod expect;
+pub mod extract;
 pub mod file;
+pub mod generate;
 pub mod history;
+pub mod import;
+pub mod lint;
 pub mod main;
+pub mod prompt;
 pub mod remember;
 pub mod request;
+pub mod secrets;
+pub mod suite;
 pub mod template;
 pub mod yaml_util;
 
@@ -0,0 +1,203 @@
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::collections::hash_map::HashMap;
+
+use libc;
+
+use super::env;
+use super::secrets::{self, Secrets};
+use super::template;
+use super::yaml_util;
+
+/// Whether to ask for missing template parameters. Unless a flag says otherwise, we only ask when
+/// stdin is a terminal, so that scripts fail instead of waiting for input.
+pub fn should_prompt(flag_prompt: bool, flag_no_prompt: bool) -> bool {
+    if flag_no_prompt {
+        false
+    } else if flag_prompt {
+        true
+    } else {
+        stdin_is_tty()
+    }
+}
+
+pub fn stdin_is_tty() -> bool {
+    unsafe { libc::isatty(0) != 0 }
+}
+
+/// Ask for a value for each parameter in the text that has no value. Values already in the named
+/// environment, or the active one if the name is empty, are offered as defaults, and the answers
+/// can be saved there as the defaults for next time. Secret values aren't shown as they're typed.
+pub fn ask_for_missing_params(text: &str, source: &str, withs: &HashMap<&str, &str>,
+                              env_name: &str, shortcuts: bool
+                              ) -> Result<HashMap<String, String>, String> {
+    let mut answers: HashMap<String, String> = HashMap::new();
+    let missing = try!(template::find_missing_params(text, source, withs, env_name, shortcuts));
+    if missing.is_empty() {
+        return Ok(answers);
+    }
+
+    let secrets = try!(Secrets::load(env_name));
+    let environment = env::load_environment(env_name).ok();
+    let mut keys: Vec<String> = Vec::new();
+    let mut vals: Vec<String> = Vec::new();
+    for param in missing.iter() {
+        printerrln!("{}", param.description.trim_right());
+        let default = environment.as_ref()
+            .and_then(|y| yaml_util::get_nested_value(y, &[param.name.as_str()]))
+            .and_then(yaml_util::scalar_to_string);
+        let hidden = secrets.is_secret_key(&param.name);
+        let answer = try!(ask(&param.name, default.as_ref().map(|s| s.as_str()), hidden));
+        keys.push(param.name.to_string());
+        vals.push(answer.to_string());
+        answers.insert(param.name.to_string(), answer);
+    }
+
+    let question =
+        if env_name.is_empty() {
+            "Save these answers as defaults in the active environment? [y/N]".to_string()
+        } else {
+            format!("Save these answers as defaults in environment {:?}? [y/N]", env_name)
+        };
+    let save = try!(ask(&question, None, false));
+    if save.to_lowercase().starts_with("y") {
+        try!(env::set_in_environment(env_name, &keys, &vals));
+    }
+    Ok(answers)
+}
+
+/// Ask a question on stderr and read the answer from stdin. An empty answer gives the default.
+/// When hidden is set, the answer and the default aren't shown.
+pub fn ask(question: &str, default: Option<&str>, hidden: bool) -> Result<String, String> {
+    let mut stderr = io::stderr();
+    let _ = match default {
+        Some(_) if hidden => write!(&mut stderr, "{} [{}]: ", question, secrets::REDACTED),
+        Some(d) => write!(&mut stderr, "{} [{}]: ", question, d),
+        None => write!(&mut stderr, "{}: ", question),
+    };
+    let _ = stderr.flush();
+
+    let mut line = String::new();
+    let result =
+        if hidden && stdin_is_tty() {
+            let _echo_off = EchoOff::new();
+            let result = io::stdin().read_line(&mut line);
+            printerrln!("");
+            result
+        } else {
+            io::stdin().read_line(&mut line)
+        };
+
+    match result {
+        Ok(0) => Err(format!("Found eof while waiting for an answer to {:?}", question)),
+        Ok(_) => {
+            let answer = line.trim_right_matches(|c: char| c == '\n' || c == '\r');
+            match default {
+                Some(d) if answer.is_empty() => Ok(d.to_string()),
+                _ => Ok(answer.to_string()),
+            }
+        },
+        Err(e) => Err(format!("Failed to read an answer to {:?} -- {}", question, e)),
+    }
+}
+
+/// Turns off the terminal's echo until it's dropped, so a secret isn't shown as it's typed. The
+/// terminal is put back the way it was, even if we panic.
+struct EchoOff {
+    saved: Option<termios::Termios>,
+}
+
+impl EchoOff {
+    fn new() -> EchoOff {
+        unsafe {
+            let mut saved: termios::Termios = mem::zeroed();
+            if termios::tcgetattr(0, &mut saved) != 0 {
+                return EchoOff { saved: None };
+            }
+            let mut quiet = saved;
+            quiet.c_lflag &= !termios::ECHO;
+            if termios::tcsetattr(0, termios::TCSANOW, &quiet) != 0 {
+                return EchoOff { saved: None };
+            }
+            EchoOff { saved: Some(saved) }
+        }
+    }
+}
+
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        if let Some(ref saved) = self.saved {
+            unsafe { termios::tcsetattr(0, termios::TCSANOW, saved); }
+        }
+    }
+}
+
+/// The parts of termios.h we need, which aren't in the libc crate
+#[cfg(target_os = "linux")]
+mod termios {
+    use libc::{c_int, c_uchar, c_uint};
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag: c_uint,
+        pub c_oflag: c_uint,
+        pub c_cflag: c_uint,
+        pub c_lflag: c_uint,
+        pub c_line: c_uchar,
+        pub c_cc: [c_uchar; 32],
+        pub c_ispeed: c_uint,
+        pub c_ospeed: c_uint,
+    }
+
+    pub const ECHO: c_uint = 0o10;
+    pub const TCSANOW: c_int = 0;
+
+    extern {
+        pub fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        pub fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod termios {
+    use libc::{c_int, c_uchar, c_ulong};
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag: c_ulong,
+        pub c_oflag: c_ulong,
+        pub c_cflag: c_ulong,
+        pub c_lflag: c_ulong,
+        pub c_cc: [c_uchar; 20],
+        pub c_ispeed: c_ulong,
+        pub c_ospeed: c_ulong,
+    }
+
+    pub const ECHO: c_ulong = 0x8;
+    pub const TCSANOW: c_int = 0;
+
+    extern {
+        pub fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        pub fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+    }
+}
+
+/// Elsewhere we don't know the layout of termios, so tcgetattr always fails and the input is shown
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod termios {
+    use libc::{c_int, c_uint};
+
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_lflag: c_uint,
+    }
+
+    pub const ECHO: c_uint = 0;
+    pub const TCSANOW: c_int = 0;
+
+    pub unsafe fn tcgetattr(_fd: c_int, _termios: *mut Termios) -> c_int { -1 }
+    pub unsafe fn tcsetattr(_fd: c_int, _action: c_int, _termios: *const Termios) -> c_int { -1 }
+}
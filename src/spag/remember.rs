@@ -8,49 +8,64 @@ use yaml_rust::YamlLoader;
 
 use super::request::SpagRequest;
 use super::file;
+use super::secrets::Secrets;
 use super::yaml_util;
 
-const REMEMBERS_DIR: &'static str = ".spag/remembers";
+fn remembers_dir() -> String {
+    file::spag_path("remembers")
+}
 
-pub fn remember(req: &SpagRequest, resp: &http::Response, remember_as: &str) -> Result<(), String> {
-    file::ensure_dir_exists(REMEMBERS_DIR);
-    let y = serialize(req, resp);
+pub fn remember(req: &SpagRequest, resp: &http::Response, remember_as: &str, secrets: &Secrets
+                ) -> Result<(), String> {
+    let remembers_dir = remembers_dir();
+    file::ensure_dir_exists(&remembers_dir);
+    let y = try!(serialize_redacted(req, resp, secrets));
     let name = file::ensure_extension(remember_as, ".yml");
-    let output_file = Path::new(REMEMBERS_DIR).join(name);
+    let output_file = Path::new(&remembers_dir).join(name);
     yaml_util::dump_yaml_file(output_file.to_str().unwrap(), &y)
 }
 
-pub fn serialize(req: &SpagRequest, resp: &http::Response) -> Yaml {
+pub fn serialize(req: &SpagRequest, resp: &http::Response) -> Result<Yaml, String> {
     let mut inner_y = YamlLoader::load_from_str("{}").unwrap().remove(0);
 
     // Add the request data
-    yaml_util::set_nested_value(&mut inner_y, &["request", "method"], req.get_method_string());
-    yaml_util::set_nested_value(&mut inner_y, &["request", "uri"], req.uri.as_str());
-    yaml_util::set_nested_value(&mut inner_y, &["request", "endpoint"], req.endpoint.as_str());
+    try!(yaml_util::set_nested_value(&mut inner_y, &["request", "method"], req.get_method_string()));
+    try!(yaml_util::set_nested_value(&mut inner_y, &["request", "uri"], req.get_uri_with_params().as_str()));
+    try!(yaml_util::set_nested_value(&mut inner_y, &["request", "endpoint"], req.endpoint.as_str()));
 
     let pretty_req_body = yaml_util::pretty_json(req.body.as_str());
-    yaml_util::set_nested_value(&mut inner_y, &["request", "body"], pretty_req_body.as_str());
+    try!(yaml_util::set_nested_value(&mut inner_y, &["request", "body"], pretty_req_body.as_str()));
 
     for (key, value) in &req.headers {
-        yaml_util::set_nested_value(&mut inner_y, &["request", "headers", key], value.as_str());
+        try!(yaml_util::set_nested_value(&mut inner_y, &["request", "headers", key], value.as_str()));
     }
 
     // Add the response data
     let pretty_resp_body = yaml_util::pretty_json(str::from_utf8(resp.get_body()).unwrap());
-    yaml_util::set_nested_value(&mut inner_y, &["response", "body"], pretty_resp_body.as_str());
+    try!(yaml_util::set_nested_value(&mut inner_y, &["response", "body"], pretty_resp_body.as_str()));
 
-    yaml_util::set_nested_value(&mut inner_y, &["response", "status"], resp.get_code().to_string().as_str());
+    try!(yaml_util::set_nested_value(&mut inner_y, &["response", "status"], resp.get_code().to_string().as_str()));
     for (key, value) in resp.get_headers() {
-        yaml_util::set_nested_value(&mut inner_y, &["response", "headers", key], value[0].as_str());
+        try!(yaml_util::set_nested_value(&mut inner_y, &["response", "headers", key], value[0].as_str()));
     }
 
-    inner_y
+    Ok(inner_y)
+}
+
+/// Like serialize(), but with the secrets redacted from the request and the response. Use this
+/// for anything written to disk.
+pub fn serialize_redacted(req: &SpagRequest, resp: &http::Response, secrets: &Secrets
+                          ) -> Result<Yaml, String> {
+    let mut y = try!(serialize(req, resp));
+    try!(secrets.redact_request(&mut y));
+    Ok(y)
 }
 
 pub fn load_remembered_request(name: &str) -> Result<Yaml, String> {
-    let matches = try!(file::find_matching_files(&file::ensure_extension(name, "yml"), REMEMBERS_DIR));
+    let remembers_dir = remembers_dir();
+    let matches = try!(file::find_matching_files(&file::ensure_extension(name, "yml"), &remembers_dir));
     if matches.len() == 0 {
-        Err(format!("Failed to find remembered request '{}' in {}", name, REMEMBERS_DIR))
+        Err(format!("Failed to find remembered request '{}' in {}", name, remembers_dir))
     } else if matches.len() == 1 {
         yaml_util::load_yaml_file(matches[0].to_str().unwrap())
     } else {
@@ -59,63 +74,223 @@ pub fn load_remembered_request(name: &str) -> Result<Yaml, String> {
 }
 
 /// Load the remembered request and grab a value from it
-pub fn find_remembered_key(remembered_name: &str, key_path: &[&str]) -> Result<String, String> {
+pub fn find_remembered_key(remembered_name: &str, key_path: &[&str], allow_json: bool
+                           ) -> Result<String, String> {
     let y = try!(load_remembered_request(remembered_name));
-    // println!("{:?}", y);
+    find_key(&y, key_path, allow_json)
+}
 
+/// Grab a value from a serialized request, like the ones returned by serialize(). Json arrays and
+/// objects in a body are refused, unless allow_json is set to get them back serialized as json.
+pub fn find_key(y: &Yaml, key_path: &[&str], allow_json: bool) -> Result<String, String> {
     // if we're grabbing a value out of the request body, load it as json
     if key_path.len() > 2 && (key_path.starts_with(&["request", "body"]) || key_path.starts_with(&["response", "body"])) {
         let yaml_key_path = &key_path[0..2];
         let json_key_path = &key_path[2..];
-        let body_string = try!(yaml_util::get_value_as_string(&y, yaml_key_path));
+        let body_string = try!(yaml_util::get_value_as_string(y, yaml_key_path));
         // println!("\nbody_string {:?}", body_string);
         if let Ok(body) = Json::from_str(&body_string) {
             // println!("body: {:?}", body);
-            // TODO: find_path only works on Json::Objects. we need to handle indexing into Json::Arrays.
-            match *try!(json_find_path(&body, json_key_path)) {
-                Json::String(ref s)  => Ok(s.to_string()),
-                Json::I64(val)       => Ok(format!("{}", val)),
-                Json::U64(val)       => Ok(format!("{}", val)),
-                Json::F64(val)       => Ok(format!("{}", val)),
-                Json::Boolean(val)   => Ok(format!("{}", val)),
-                Json::Null           => Ok("null".to_string()),
-                Json::Array(_) | Json::Object(_) => {
-                    Err(format!("Refusing to interpolate json array or object in template"))
-                },
+            let value = try!(json_find_path(&body, json_key_path));
+            if allow_json && (value.is_array() || value.is_object()) {
+                Ok(value.to_string())
+            } else {
+                json_to_string(&value)
             }
         } else {
-            Err(format!("Failed to load body as json for {:?} in remembered request {}", key_path, remembered_name))
+            Err(format!("Failed to load body as json for {:?}", key_path))
         }
     } else {
-        yaml_util::get_value_as_string(&y, key_path)
+        yaml_util::get_value_as_string(y, key_path)
+    }
+}
+
+/// Split a key path like "things[?name==\"a.b\"].id" into ["things", "[?name==\"a.b\"]", "id"].
+/// Dots inside of a [...] filter don't split the key path.
+pub fn split_key_path(key_path: &str) -> Vec<&str> {
+    let mut result: Vec<&str> = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    for (i, c) in key_path.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => { quote = None; },
+            (Some(_), _) => {},
+            (None, '"') | (None, '\'') if depth > 0 => { quote = Some(c); },
+            (None, '[') => {
+                if depth == 0 && i > start {
+                    result.push(&key_path[start..i]);
+                    start = i;
+                }
+                depth += 1;
+            },
+            (None, ']') if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    result.push(&key_path[start..i + 1]);
+                    start = i + 1;
+                }
+            },
+            (None, '.') if depth == 0 => {
+                if i > start {
+                    result.push(&key_path[start..i]);
+                }
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    if start < key_path.len() {
+        result.push(&key_path[start..]);
     }
+    result
 }
 
-/// Grab a value out of some json. This handles array indexing properly.
-pub fn json_find_path<'a>(data: &'a Json, key_path: &[&str]) -> Result<&'a Json, String> {
+/// Grab a value out of some json. Besides object keys, each key in the path may be:
+///     - an array index, like "0", or "-1" for the last item
+///     - a wildcard "*", which applies the rest of the key path to every item in an array
+///       or object, and collects the results into an array
+///     - a filter like [?name=="foo"], which picks the first array item whose key equals the value
+///     - "length", the number of items in an array or object, or characters in a string
+pub fn json_find_path(data: &Json, key_path: &[&str]) -> Result<Json, String> {
     if key_path.is_empty() {
        return Err("Empty json key".to_string());
     }
+    find_json(data, key_path, key_path).map_err(|e| e.message())
+}
+
+/// Why a key path found nothing. A key missing from an object is kept apart from other
+/// problems, so that a wildcard can skip the items that don't have the key.
+enum NotFound {
+    MissingKey(String),
+    Invalid(String),
+}
+
+impl NotFound {
+    fn message(self) -> String {
+        match self {
+            NotFound::MissingKey(message) => message,
+            NotFound::Invalid(message) => message,
+        }
+    }
+}
 
-    let mut target = data;
-    for key in key_path.iter() {
-        if target.is_array() {
-            match key.parse::<usize>() {
-                Ok(x) => {
-                    if x < target.as_array().unwrap().len() {
-                        target = &target[x];
-                    } else {
-                        return Err(format!("Index {} out of bounds for key path {:?}", key, key_path));
-                    }
+fn find_json(target: &Json, keys: &[&str], key_path: &[&str]) -> Result<Json, NotFound> {
+    if keys.is_empty() {
+        return Ok(target.clone());
+    }
+    let key = keys[0];
+    let rest = &keys[1..];
+    if key == "*" {
+        let items: Vec<&Json> =
+            match *target {
+                Json::Array(ref arr) => arr.iter().collect(),
+                Json::Object(ref obj) => obj.values().collect(),
+                _ => {
+                    return Err(NotFound::Invalid(format!(
+                        "Wildcard '*' used on a value that is not an array or object for key path {:?}",
+                        key_path)));
                 },
-                Err(_) => { return Err(format!("Invalid array index '{}' for key path {:?}", key, key_path)); },
             };
-        } else {
-            match target.find(*key) {
-                Some(t) => { target = t; },
-                None => { return Err(format!("Invalid key '{}'", key)); },
+        // apply the key path to each item, up to a "length" which counts the results
+        let end = rest.iter().position(|&k| k == "length").unwrap_or(rest.len());
+        let mut results: Vec<Json> = Vec::new();
+        for item in items {
+            match find_json(item, &rest[..end], key_path) {
+                Ok(value) => { results.push(value); },
+                Err(NotFound::MissingKey(_)) => {},
+                Err(e) => { return Err(e); },
+            }
+        }
+        find_json(&Json::Array(results), &rest[end..], key_path)
+    } else if key.starts_with("[?") && key.ends_with(']') {
+        let (filter_path, expected) = try!(parse_filter(key).map_err(NotFound::Invalid));
+        let filter_path = split_key_path(filter_path);
+        let arr = match target.as_array() {
+            Some(arr) => arr,
+            None => {
+                return Err(NotFound::Invalid(format!(
+                    "Filter {} used on a value that is not an array for key path {:?}", key, key_path)));
+            },
+        };
+        let found = arr.iter().find(|item| {
+            match find_json(item, &filter_path, key_path) {
+                Ok(ref val) => json_to_string(val).ok().map_or(false, |s| s == expected),
+                Err(_) => false,
             }
+        });
+        match found {
+            Some(item) => find_json(item, rest, key_path),
+            None => Err(NotFound::Invalid(format!("No item matches filter {} for key path {:?}", key, key_path))),
+        }
+    } else if target.is_array() {
+        let arr = target.as_array().unwrap();
+        if key == "length" {
+            return find_json(&Json::U64(arr.len() as u64), rest, key_path);
+        }
+        match key.parse::<i64>() {
+            Ok(x) => {
+                // negative indices count back from the end of the array
+                let index = if x < 0 { arr.len() as i64 + x } else { x };
+                if index >= 0 && (index as usize) < arr.len() {
+                    find_json(&arr[index as usize], rest, key_path)
+                } else {
+                    Err(NotFound::Invalid(format!("Index {} out of bounds for key path {:?}", key, key_path)))
+                }
+            },
+            Err(_) => {
+                Err(NotFound::Invalid(format!("Invalid array index '{}' for key path {:?}", key, key_path)))
+            },
+        }
+    } else {
+        match (target.find(key), target) {
+            (Some(t), _) => find_json(t, rest, key_path),
+            (None, &Json::Object(ref obj)) if key == "length" => {
+                find_json(&Json::U64(obj.len() as u64), rest, key_path)
+            },
+            (None, &Json::String(ref s)) if key == "length" => {
+                find_json(&Json::U64(s.chars().count() as u64), rest, key_path)
+            },
+            (None, &Json::Object(_)) => Err(NotFound::MissingKey(format!("Invalid key '{}'", key))),
+            (None, _) => Err(NotFound::Invalid(format!("Invalid key '{}'", key))),
         }
     }
-    Ok(target)
+}
+
+/// Parse a filter like [?name=="foo"] into the key path and value, like ("name", "foo").
+/// The value may be in single or double quotes, or bare like [?count==2].
+fn parse_filter(filter: &str) -> Result<(&str, &str), String> {
+    let inner = &filter[2..filter.len() - 1];
+    let i = match inner.find("==") {
+        Some(i) => i,
+        None => { return Err(format!("Invalid filter {}. Expected something like [?name==\"foo\"]", filter)); },
+    };
+    let path = inner[..i].trim();
+    let value = inner[i + 2..].trim();
+    let value =
+        if value.len() >= 2 && (value.starts_with('"') && value.ends_with('"')
+                                || value.starts_with('\'') && value.ends_with('\'')) {
+            &value[1..value.len() - 1]
+        } else {
+            value
+        };
+    if path.is_empty() {
+        return Err(format!("Invalid filter {}. Expected something like [?name==\"foo\"]", filter));
+    }
+    Ok((path, value))
+}
+
+/// Convert a json value to the string we would substitute into a template
+pub fn json_to_string(data: &Json) -> Result<String, String> {
+    match *data {
+        Json::String(ref s)  => Ok(s.to_string()),
+        Json::I64(val)       => Ok(format!("{}", val)),
+        Json::U64(val)       => Ok(format!("{}", val)),
+        Json::F64(val)       => Ok(format!("{}", val)),
+        Json::Boolean(val)   => Ok(format!("{}", val)),
+        Json::Null           => Ok("null".to_string()),
+        Json::Array(_) | Json::Object(_) => {
+            Err(format!("Refusing to interpolate json array or object in template"))
+        },
+    }
 }
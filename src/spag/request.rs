@@ -7,21 +7,52 @@ use std::collections::hash_map::HashMap;
 use curl::http;
 use curl::http::handle::Method;
 use yaml_rust::Yaml;
+use super::easy;
 use super::file;
 use super::yaml_util;
 
 
-/// Split "Content-type: application/json" into vec!["Content-type", "application/json"]
+/// Split "Content-type: application/json" into ("Content-type", "application/json")
+/// Only the first ':' is significant, so values like urls are kept whole.
 pub fn split_header<'a>(header: &'a str) -> Result<(&'a str, &'a str), String> {
-    // Strip out any spaces and separate the components
-    let h: Vec<&str> = header.split(":").map(|s| s.trim()).collect();
+    // If the header didn't have a colon, or had no name before it, it's invalid
+    match header.find(':') {
+        Some(i) if !header[..i].trim().is_empty() => {
+            // Strip out any spaces around the components
+            Ok((header[..i].trim(), header[i + 1..].trim()))
+        },
+        _ => Err(format!("Invalid header {:?}", header)),
+    }
+}
 
-    // If the header didn't have a colon, or had more than 1, it's invalid
-    if h.len() != 2 {
-        Err(format!("Invalid header {:?}", header))
-    } else {
-        Ok((h[0], h[1]))
+/// Split "key=value" into ("key", "value"). Only the first '=' is significant, so the value may
+/// contain more '=' characters.
+pub fn split_param<'a>(param: &'a str) -> Result<(&'a str, &'a str), String> {
+    match param.find('=') {
+        Some(i) if i > 0 => Ok((&param[..i], &param[i + 1..])),
+        _ => Err(format!("Invalid query parameter {:?}", param)),
+    }
+}
+
+/// Percent-encode a string for use in a url. Only unreserved characters are left alone.
+///     url_encode("a b/c") -> "a%20b%2Fc"
+pub fn url_encode(s: &str) -> String {
+    let mut result = String::new();
+    for b in s.bytes() {
+        match b {
+            b'a' ... b'z' | b'A' ... b'Z' | b'0' ... b'9' | b'-' | b'_' | b'.' | b'~' => {
+                result.push(b as char);
+            },
+            _ => { result.push_str(&format!("%{:02X}", b)); },
+        }
     }
+    result
+}
+
+/// Quote a string for a POSIX shell. Single quotes inside are closed, escaped, and reopened.
+///     shell_quote("it's") -> "'it'\\''s'"
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace("'", "'\\''"))
 }
 
 pub fn get_request_filename(name: &str, dir: &str) -> Result<String, String> {
@@ -43,57 +74,63 @@ pub fn load_request_file(name: &str, dir: &str) -> Result<Yaml, String> {
     yaml_util::load_yaml_file(&filename)
 }
 
-pub fn method_from_str(s: &str) -> Method {
-    match s.to_ascii_lowercase().as_str() {
-        "options" => Method::Options,
-        "get" => Method::Get,
-        "head" => Method::Head,
-        "post" => Method::Post,
-        "put" => Method::Put,
-        "patch" => Method::Patch,
-        "delete" => Method::Delete,
-        "trace" => Method::Trace,
-        "connect" => Method::Connect,
-        _ => { panic!(format!("Invalid method string {}", s)); }
-    }
-}
-
-pub fn method_to_str(m: Method) -> &'static str {
-    match m {
-        Method::Options => "OPTIONS",
-        Method::Get => "GET",
-        Method::Head => "HEAD",
-        Method::Post => "POST",
-        Method::Put => "PUT",
-        Method::Patch => "PATCH",
-        Method::Delete => "DELETE",
-        Method::Trace => "TRACE",
-        Method::Connect => "CONNECT",
+/// Parse a method name, in any case. Any http token is allowed, not just the standard methods,
+/// and it's sent in upper case.
+pub fn method_from_str(s: &str) -> Result<String, String> {
+    let is_token_char = |c: char| {
+        match c {
+            'a' ... 'z' | 'A' ... 'Z' | '0' ... '9' => true,
+            c => "!#$%&'*+-.^_`|~".contains(c),
+        }
+    };
+    if s.is_empty() || !s.chars().all(is_token_char) {
+        Err(format!("Invalid http method {:?}. A method may only have letters, digits and any of \
+                     !#$%&'*+-.^_`|~", s))
+    } else {
+        Ok(s.to_ascii_uppercase())
     }
 }
 
 pub struct SpagRequest {
-    pub method: Method,
+    pub method: String,
     pub headers: HashMap<String, String>,
     pub body: String,
     pub endpoint: String,
     pub uri: String,
+    pub params: Vec<(String, String)>,
 }
 
 impl SpagRequest {
 
-    pub fn new(method: Method, endpoint: String, uri: String) -> SpagRequest {
+    pub fn new(method: String, endpoint: String, uri: String) -> SpagRequest {
         SpagRequest {
             method: method,
             body: String::new(),
             headers: HashMap::new(),
             endpoint: endpoint,
-            uri: uri
+            uri: uri,
+            params: Vec::new(),
         }
     }
 
-    pub fn get_method_string(&self) -> &'static str {
-        method_to_str(self.method)
+    pub fn get_method_string(&self) -> &str {
+        &self.method
+    }
+
+    /// The curl bindings only know these methods. Others are sent with easy::send()
+    fn get_curl_method(&self) -> Option<Method> {
+        match self.method.as_str() {
+            "OPTIONS" => Some(Method::Options),
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "PATCH" => Some(Method::Patch),
+            "DELETE" => Some(Method::Delete),
+            "TRACE" => Some(Method::Trace),
+            "CONNECT" => Some(Method::Connect),
+            _ => None,
+        }
     }
 
     pub fn set_body(&mut self, body: String) {
@@ -118,13 +155,81 @@ impl SpagRequest {
         Ok(())
     }
 
-    pub fn prepare<'a, 'b>(&'b self, handle: &'a mut http::Handle) -> http::Request<'a, 'b> {
-        let uri = self.endpoint.to_string() + &self.uri;
-        let headers = self.headers.iter().map(|(a, b)| (a.as_str(), b.as_str()));
-        http::Request::new(handle, self.method)
-            .uri(uri.to_string())
-            .headers(headers)
-            .body(&self.body)
+    /// Params is some iterable of (key, value) pairs. Repeated keys are kept, and each will show
+    /// up as a separate query parameter.
+    pub fn add_params<I: Iterator<Item=(String, String)>>(&mut self, params: I) {
+        self.params.extend(params);
+    }
+
+    /// Returns the uri with the query parameters percent-encoded and appended to it
+    pub fn get_uri_with_params(&self) -> String {
+        if self.params.is_empty() {
+            return self.uri.to_string();
+        }
+        let query: Vec<String> = self.params.iter()
+            .map(|&(ref k, ref v)| format!("{}={}", url_encode(k), url_encode(v)))
+            .collect();
+        let separator =
+            if !self.uri.contains('?') {
+                "?"
+            } else if self.uri.ends_with('?') || self.uri.ends_with('&') {
+                ""
+            } else {
+                "&"
+            };
+        format!("{}{}{}", self.uri, separator, query.join("&"))
+    }
+
+    /// Describe the request as it would be sent: the method and url, headers, and body
+    pub fn describe(&self) -> String {
+        let mut result = format!("{} {}{}\n", self.get_method_string(), self.endpoint,
+                                 self.get_uri_with_params());
+        let mut headers: Vec<(&String, &String)> = self.headers.iter().collect();
+        headers.sort();
+        for (k, v) in headers {
+            result.push_str(&format!("{}: {}\n", k, v));
+        }
+        result.push_str(&format!("Body:\n{}", self.body));
+        result
+    }
+
+    /// Render the request as a curl command that can be run without spag
+    pub fn to_curl(&self) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        let url = self.endpoint.to_string() + &self.get_uri_with_params();
+        // curl -X HEAD waits for a body that never comes, so use --head
+        if self.get_method_string() == "HEAD" {
+            lines.push(format!("curl --head {}", shell_quote(&url)));
+        } else {
+            lines.push(format!("curl -X {} {}", self.get_method_string(), shell_quote(&url)));
+        }
+
+        let mut headers: Vec<(&String, &String)> = self.headers.iter().collect();
+        headers.sort();
+        for (k, v) in headers {
+            lines.push(format!("  -H {}", shell_quote(&format!("{}: {}", k, v))));
+        }
+        if !self.body.is_empty() {
+            lines.push(format!("  --data-raw {}", shell_quote(&self.body)));
+        }
+        lines.join(" \\\n")
+    }
+
+    pub fn send(&self) -> Result<http::Response, String> {
+        let uri = self.endpoint.to_string() + &self.get_uri_with_params();
+        match self.get_curl_method() {
+            Some(method) => {
+                let mut handle = http::handle();
+                let headers = self.headers.iter().map(|(a, b)| (a.as_str(), b.as_str()));
+                http::Request::new(&mut handle, method)
+                    .uri(uri.to_string())
+                    .headers(headers)
+                    .body(&self.body)
+                    .exec()
+                    .map_err(|e| format!("{}", e))
+            },
+            None => easy::send(&self.method, &uri, &self.headers, &self.body),
+        }
     }
 }
 
@@ -135,6 +240,7 @@ impl std::fmt::Debug for SpagRequest {
             .field("headers", &self.headers)
             .field("endpoint", &self.endpoint)
             .field("uri", &self.uri)
+            .field("params", &self.params)
             .field("body", &self.body)
             .finish()
     }
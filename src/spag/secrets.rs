@@ -0,0 +1,210 @@
+use rustc_serialize::json::Json;
+use yaml_rust::Yaml;
+
+use super::env;
+use super::import;
+use super::yaml_util;
+
+/// What secret values are replaced with
+pub const REDACTED: &'static str = "********";
+
+/// Secrets are listed in an environment by key path, like:
+///
+///     secrets:
+///         - headers.X-Auth-Token
+///         - password
+///
+/// or as a comma separated string, like "headers.X-Auth-Token,password". The values at those key
+/// paths are never written out. Listing "headers.<name>" also hides that header whether it comes
+/// from the environment, a request file, or the command line.
+#[derive(Debug)]
+pub struct Secrets {
+    key_paths: Vec<String>,
+    values: Vec<String>,
+    header_names: Vec<String>,
+}
+
+/// Returns the key paths listed under "secrets" in an environment, from a list or a comma
+/// separated string
+pub fn secret_key_paths(y: &Yaml) -> Vec<String> {
+    match yaml_util::get_nested_value(y, &["secrets"]) {
+        Some(&Yaml::Array(ref arr)) => {
+            arr.iter().filter_map(|k| k.as_str()).map(|k| k.trim().to_string()).collect()
+        },
+        Some(&Yaml::String(ref s)) => {
+            s.split(',').map(|k| k.trim().to_string()).filter(|k| !k.is_empty()).collect()
+        },
+        _ => Vec::new(),
+    }
+}
+
+impl Secrets {
+
+    /// Find the secrets listed in an environment
+    pub fn from_environment(y: &Yaml) -> Secrets {
+        let key_paths = secret_key_paths(y);
+        let mut values: Vec<String> = Vec::new();
+        let mut header_names: Vec<String> = Vec::new();
+        for key_path in key_paths.iter() {
+            let parts: Vec<&str> = key_path.split('.').collect();
+            if parts.len() == 2 && parts[0] == "headers" {
+                header_names.push(parts[1].to_lowercase());
+            }
+            // don't try to redact empty values. that would be everywhere.
+            if let Some(value) = yaml_util::get_nested_value(y, &parts).and_then(yaml_util::scalar_to_string) {
+                if !value.is_empty() {
+                    values.push(value);
+                }
+            }
+        }
+        Secrets { key_paths: key_paths, values: values, header_names: header_names }
+    }
+
+    /// Find the secrets listed in the named environment, or the active one if the name is empty.
+    /// If the environment can't be loaded, we can't know what to redact, so nothing should be saved.
+    pub fn load(env_name: &str) -> Result<Secrets, String> {
+        let y = try!(env::load_environment(env_name)
+            .map_err(|e| format!("Failed to load the secrets in the environment -- {}", e)));
+        Ok(Secrets::from_environment(&y))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.key_paths.is_empty()
+    }
+
+    pub fn is_secret_key(&self, key_path: &str) -> bool {
+        self.key_paths.iter().any(|k| k == key_path)
+    }
+
+    pub fn is_secret_header(&self, name: &str) -> bool {
+        self.header_names.contains(&name.to_lowercase())
+    }
+
+    /// Only whole values are secret, so that a short secret doesn't hide every word that
+    /// happens to contain it
+    pub fn is_secret_value(&self, value: &str) -> bool {
+        self.values.iter().any(|v| v == value)
+    }
+
+    /// Redact a header value. Besides the whole value, this checks the credentials after the
+    /// scheme, like the token in "Bearer abcde".
+    pub fn redact_header(&self, name: &str, value: &str) -> String {
+        let credentials = value.splitn(2, ' ').nth(1).unwrap_or("");
+        if self.is_secret_header(name) || self.is_secret_value(value) || self.is_secret_value(credentials) {
+            REDACTED.to_string()
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// Redact a url, like "/users/hunter2?token=abcde". Path segments and query values are
+    /// decoded before they're compared.
+    pub fn redact_url(&self, url: &str) -> String {
+        let (path, query) =
+            match url.find('?') {
+                Some(i) => (&url[..i], Some(&url[i + 1..])),
+                None => (url, None),
+            };
+        let segments: Vec<String> = path.split('/').map(|s| self.redact_encoded(s)).collect();
+        let mut result = segments.join("/");
+        if let Some(query) = query {
+            result.push('?');
+            result.push_str(&self.redact_form(query));
+        }
+        result
+    }
+
+    /// Redact a body. JSON values are compared one by one, and anything else is treated as
+    /// form data, like "user=pglbutt&password=hunter2".
+    pub fn redact_body(&self, body: &str) -> String {
+        match Json::from_str(body) {
+            Ok(json) => {
+                let redacted = self.redact_json(&json);
+                if redacted == json { body.to_string() } else { format!("{}", redacted.pretty()) }
+            },
+            Err(_) => self.redact_form(body),
+        }
+    }
+
+    fn redact_form(&self, text: &str) -> String {
+        let pairs: Vec<String> = text.split('&')
+            .map(|pair| match pair.find('=') {
+                Some(i) => format!("{}={}", &pair[..i], self.redact_encoded(&pair[i + 1..])),
+                None => self.redact_encoded(pair),
+            })
+            .collect();
+        pairs.join("&")
+    }
+
+    fn redact_encoded(&self, value: &str) -> String {
+        if self.is_secret_value(value) || self.is_secret_value(&import::url_decode(value)) {
+            REDACTED.to_string()
+        } else {
+            value.to_string()
+        }
+    }
+
+    fn redact_json(&self, json: &Json) -> Json {
+        match *json {
+            Json::String(ref s) if self.is_secret_value(s) => Json::String(REDACTED.to_string()),
+            Json::I64(_) | Json::U64(_) | Json::F64(_) if self.is_secret_value(&json.to_string()) => {
+                Json::String(REDACTED.to_string())
+            },
+            Json::Array(ref arr) => Json::Array(arr.iter().map(|j| self.redact_json(j)).collect()),
+            Json::Object(ref obj) => {
+                Json::Object(obj.iter().map(|(k, v)| (k.to_string(), self.redact_json(v))).collect())
+            },
+            ref other => other.clone(),
+        }
+    }
+
+    /// Redact a request and its response, serialized by remember::serialize()
+    pub fn redact_request(&self, y: &mut Yaml) -> Result<(), String> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        for key in ["endpoint", "uri"].iter() {
+            let url = yaml_util::get_nested_value(y, &["request", *key])
+                .and_then(|v| v.as_str())
+                .map(|v| self.redact_url(v));
+            if let Some(url) = url {
+                try!(yaml_util::set_nested_value(y, &["request", *key], &url));
+            }
+        }
+        for part in ["request", "response"].iter() {
+            let body = yaml_util::get_nested_value(y, &[*part, "body"])
+                .and_then(|v| v.as_str())
+                .map(|v| self.redact_body(v));
+            if let Some(body) = body {
+                try!(yaml_util::set_nested_value(y, &[*part, "body"], &body));
+            }
+
+            let mut headers: Vec<(String, String)> = Vec::new();
+            if let Some(&Yaml::Hash(ref h)) = yaml_util::get_nested_value(y, &[*part, "headers"]) {
+                for (k, v) in h.iter() {
+                    if let (Some(name), Some(value)) = (k.as_str(), v.as_str()) {
+                        headers.push((name.to_string(), self.redact_header(name, value)));
+                    }
+                }
+            }
+            for (name, value) in headers {
+                try!(yaml_util::set_nested_value(y, &[*part, "headers", &name], &value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Redact the values at each secret key path in an environment. Returns the key paths that
+    /// were redacted.
+    pub fn redact_environment(&self, y: &mut Yaml) -> Result<Vec<String>, String> {
+        let mut result: Vec<String> = Vec::new();
+        for key_path in self.key_paths.iter() {
+            let parts: Vec<&str> = key_path.split('.').collect();
+            if yaml_util::get_nested_value(y, &parts).is_some() {
+                try!(yaml_util::set_nested_value(y, &parts, REDACTED));
+                result.push(key_path.to_string());
+            }
+        }
+        Ok(result)
+    }
+}
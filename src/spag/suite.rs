@@ -0,0 +1,79 @@
+use std::collections::hash_map::HashMap;
+
+use yaml_rust::Yaml;
+
+use super::yaml_util;
+
+/// One request to send as part of a suite
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    pub request: String,
+    pub withs: HashMap<String, String>,
+    pub remember_as: String,
+}
+
+/// Load a suite file, which lists request files to send in order:
+///
+///     steps:
+///         - auth
+///         - request: post_thing
+///           with:
+///               thing_id: wumbo
+///           remember_as: created
+///         - request: get_thing
+///           with:
+///               thing_id: "{{ created.response.body.id }}"
+///
+/// A step may be just the name of a request file if it needs nothing else.
+pub fn load_suite(filename: &str) -> Result<Vec<Step>, String> {
+    let y = try!(yaml_util::load_yaml_file(filename));
+    parse_suite(&y)
+}
+
+pub fn parse_suite(y: &Yaml) -> Result<Vec<Step>, String> {
+    let steps = match yaml_util::get_nested_value(y, &["steps"]) {
+        Some(&Yaml::Array(ref arr)) => arr,
+        Some(_) => { return Err("Invalid steps in suite file. Expected a list.".to_string()); },
+        None => { return Err("Missing key [\"steps\"] in suite file".to_string()); },
+    };
+    let mut result: Vec<Step> = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        result.push(try!(parse_step(step).map_err(|e| format!("Invalid step #{} -- {}", i + 1, e))));
+    }
+    Ok(result)
+}
+
+fn parse_step(y: &Yaml) -> Result<Step, String> {
+    if let &Yaml::String(ref request) = y {
+        return Ok(Step {
+            request: request.to_string(),
+            withs: HashMap::new(),
+            remember_as: String::new(),
+        });
+    }
+    let request = try!(yaml_util::get_value_as_string(y, &["request"]));
+    let remember_as =
+        match yaml_util::get_nested_value(y, &["remember_as"]) {
+            Some(_) => try!(yaml_util::get_value_as_string(y, &["remember_as"])),
+            None => String::new(),
+        };
+    let mut withs: HashMap<String, String> = HashMap::new();
+    match yaml_util::get_nested_value(y, &["with"]) {
+        Some(&Yaml::Hash(ref h)) => {
+            for (k, v) in h.iter() {
+                let key = try!(yaml_util::scalar_to_string(k)
+                    .ok_or(format!("Invalid with key {}", yaml_util::show_yaml(k))));
+                let value = try!(yaml_util::scalar_to_string(v)
+                    .ok_or(format!("Invalid value {} for with key {:?}", yaml_util::show_yaml(v), key)));
+                withs.insert(key, value);
+            }
+        },
+        Some(_) => { return Err("Expected a map of with keys and values".to_string()); },
+        None => {},
+    }
+    Ok(Step {
+        request: request,
+        withs: withs,
+        remember_as: remember_as,
+    })
+}
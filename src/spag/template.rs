@@ -1,10 +1,14 @@
 use std;
 use std::collections::hash_map::HashMap;
 use yaml_rust::Yaml;
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use rustc_serialize::json::Json;
 
 use super::env;
+use super::generate;
 use super::yaml_util;
 use super::remember;
+use super::request;
 
 /// These are the characters allowed to be used in template list items.  For shortcut syntax like
 /// "@<name>", we'll stop reading <name> once we see a character outside of this list.
@@ -17,7 +21,7 @@ const VALID_ITEM_NAME_CHARS: &'static str =
 
 #[derive(Debug, PartialEq)]
 pub enum Token<'a> {
-    Substitute(Vec<Choice<'a>>),
+    Substitute(Vec<Choice<'a>>, Vec<Filter>),
     Text(&'a str),
 }
 
@@ -26,14 +30,130 @@ pub enum Choice<'a> {
     With(&'a str),
     Env(&'a str, Vec<&'a str>),
     Request(&'a str, Vec<String>),
+    ProcessEnv(&'a str),
+    Function(&'a str, Vec<&'a str>),
     DefaultVal(&'a str),
 }
 
+/// A syntax error in a template, found at the given byte offset in the text
+#[derive(Debug, PartialEq)]
+pub struct TemplateError {
+    pub message: String,
+    pub offset: usize,
+}
+
+impl TemplateError {
+    /// Show the error along with the line it's on, and a caret under where it was found, like:
+    ///
+    ///     --data:1:14: Invalid character ',' found in template item
+    ///     {"id": "{{ a, }}"}
+    ///                 ^
+    pub fn render(&self, text: &str, source: &str) -> String {
+        let offset = std::cmp::min(self.offset, text.len());
+        let line_start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = text[offset..].find('\n').map(|i| offset + i).unwrap_or(text.len());
+        let line_number = text[..line_start].matches('\n').count() + 1;
+        let before = &text[line_start..offset];
+        let column = before.chars().count() + 1;
+        // keep any tabs, so the caret lines up with the text
+        let padding: String = before.chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        format!("{}:{}:{}: {}\n{}\n{}^", source, line_number, column, self.message,
+                &text[line_start..line_end], padding)
+    }
+}
+
+/// A template parameter with no value, that could be given with "--with <name> <value>"
+#[derive(Debug, PartialEq)]
+pub struct MissingParam {
+    pub name: String,
+    pub description: String,
+}
+
+/// Filters are applied in order to the substituted value, like {{ name | trim | urlencode }}
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Filter {
+    UrlEncode,
+    FormEncode,
+    Base64,
+    Base64Decode,
+    JsonEscape,
+    Upper,
+    Lower,
+    Trim,
+    Json,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Result<Filter, String> {
+        match name {
+            "urlencode" => Ok(Filter::UrlEncode),
+            "formencode" => Ok(Filter::FormEncode),
+            "base64" => Ok(Filter::Base64),
+            "base64decode" => Ok(Filter::Base64Decode),
+            "json_escape" => Ok(Filter::JsonEscape),
+            "upper" => Ok(Filter::Upper),
+            "lower" => Ok(Filter::Lower),
+            "trim" => Ok(Filter::Trim),
+            "json" => Ok(Filter::Json),
+            _ => Err(format!("Unknown template filter \"{}\". Expected one of urlencode, formencode, \
+                              base64, base64decode, json_escape, upper, lower, trim or json", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Filter::UrlEncode => "urlencode",
+            Filter::FormEncode => "formencode",
+            Filter::Base64 => "base64",
+            Filter::Base64Decode => "base64decode",
+            Filter::JsonEscape => "json_escape",
+            Filter::Upper => "upper",
+            Filter::Lower => "lower",
+            Filter::Trim => "trim",
+            Filter::Json => "json",
+        }
+    }
+
+    pub fn apply(&self, val: &str) -> Result<String, String> {
+        match *self {
+            Filter::UrlEncode => Ok(request::url_encode(val)),
+            Filter::FormEncode => {
+                // like urlencode, but spaces become '+' as in an html form body
+                let parts: Vec<String> = val.split(' ').map(request::url_encode).collect();
+                Ok(parts.join("+"))
+            },
+            Filter::Base64 => Ok(val.as_bytes().to_base64(STANDARD)),
+            Filter::Base64Decode => {
+                let bytes = try!(val.from_base64()
+                    .map_err(|e| format!("Failed to base64 decode {:?} -- {}", val, e)));
+                String::from_utf8(bytes)
+                    .map_err(|_| format!("Base64 decoded value of {:?} is not valid utf-8", val))
+            },
+            Filter::JsonEscape => {
+                // encode as a json string and drop the surrounding quotes
+                let s = Json::String(val.to_string()).to_string();
+                Ok(s[1..s.len() - 1].to_string())
+            },
+            Filter::Upper => Ok(val.to_uppercase()),
+            Filter::Lower => Ok(val.to_lowercase()),
+            Filter::Trim => Ok(val.trim().to_string()),
+            // json arrays and objects are allowed by substitute() with this filter, and are already
+            // json. anything else is encoded as a json string.
+            Filter::Json => {
+                match Json::from_str(val) {
+                    Ok(ref j) if j.is_array() || j.is_object() => Ok(val.to_string()),
+                    _ => Ok(Json::String(val.to_string()).to_string()),
+                }
+            },
+        }
+    }
+}
+
 /// Untemplate the given string
 ///
 /// The strategy this uses is to a take a string like:
 ///
-///     "aaaa{{thing, [env].thing.id, last.response.body.thing}}"
+///     "aaaa{{thing, [env].thing.id, last.response.body.thing, $THING}}"
 ///
 /// and convert that to tokens:
 ///
@@ -43,21 +163,102 @@ pub enum Choice<'a> {
 ///             With("aaaa"),
 ///             Env("env", ["thing", "id"]),
 ///             Request("last", ["response", "body", "thing"]),
-///         ])
+///             ProcessEnv("THING"),
+///         ], [])
 ///     ]
 ///
-/// and then do substitutions and build the resulting string
-pub fn untemplate(text: &str, withs: &HashMap<&str, &str>, shortcuts: bool
-                  ) -> Result<String, String> {
-    let tokens = try!(Tokenizer::new(text, shortcuts).tokenize());
+/// and then do substitutions and build the resulting string.
+///
+/// Use `\{{` for a literal "{{", and `@@` for a literal "@" when shortcuts are enabled.
+///
+/// The source, like a file name or "--data", is used to show where a syntax error was found.
+/// Environment keys without an environment name, like `[].thing_id`, are found in the named
+/// environment, or the active environment if the name is empty.
+pub fn untemplate(text: &str, source: &str, withs: &HashMap<&str, &str>, env_name: &str,
+                  shortcuts: bool) -> Result<String, String> {
+    untemplate_text(text, source, withs, env_name, shortcuts, false)
+}
+
+/// Untemplate a request body. Json arrays and objects from a response body are substituted as
+/// json here, where they're refused by untemplate() unless the json filter is used.
+pub fn untemplate_body(text: &str, source: &str, withs: &HashMap<&str, &str>, env_name: &str,
+                       shortcuts: bool) -> Result<String, String> {
+    untemplate_text(text, source, withs, env_name, shortcuts, true)
+}
+
+/// Untemplate the text of a request file. Only the body section allows json arrays and objects.
+pub fn untemplate_request_file(text: &str, source: &str, withs: &HashMap<&str, &str>,
+                               env_name: &str, shortcuts: bool) -> Result<String, String> {
+    let mut result = String::new();
+    let mut section_start = 0;
+    for (section, is_body) in split_yaml_sections(text) {
+        // the error offset is within the section, but we show the line in the whole file
+        let tokens = try!(Tokenizer::new(section, shortcuts).tokenize().map_err(|e| {
+            let e = TemplateError { message: e.message, offset: section_start + e.offset };
+            e.render(text, source)
+        }));
+        result.push_str(&try!(substitute_tokens(tokens, withs, env_name, is_body)));
+        section_start += section.len();
+    }
+    Ok(result)
+}
+
+/// Split yaml text into sections at each top-level key, so we know which text is the body.
+/// Returns each section of text paired with whether it's the "body" section.
+pub fn split_yaml_sections(text: &str) -> Vec<(&str, bool)> {
+    let mut result: Vec<(&str, bool)> = Vec::new();
+    let mut section_start = 0;
+    let mut in_body = false;
+    let mut line_start = 0;
+    while line_start < text.len() {
+        let line = &text[line_start..];
+        let is_top_level_key = line.starts_with(|c: char| !c.is_whitespace() && c != '#' && c != '-');
+        if is_top_level_key {
+            let is_body = ["body:", "\"body\":", "'body':"].iter().any(|&k| line.starts_with(k));
+            if is_body != in_body {
+                if line_start > section_start {
+                    result.push((&text[section_start..line_start], in_body));
+                }
+                section_start = line_start;
+                in_body = is_body;
+            }
+        }
+        line_start = match line.find('\n') {
+            Some(i) => line_start + i + 1,
+            None => text.len(),
+        };
+    }
+    if section_start < text.len() || result.is_empty() {
+        result.push((&text[section_start..], in_body));
+    }
+    result
+}
+
+fn untemplate_text(text: &str, source: &str, withs: &HashMap<&str, &str>, env_name: &str,
+                   shortcuts: bool, allow_json: bool) -> Result<String, String> {
+    let tokens = try!(tokenize(text, source, shortcuts));
+    substitute_tokens(tokens, withs, env_name, allow_json)
+}
+
+/// Tokenize the text, showing where any syntax error was found
+fn tokenize<'a>(text: &'a str, source: &str, shortcuts: bool) -> Result<Vec<Token<'a>>, String> {
+    Tokenizer::new(text, shortcuts).tokenize().map_err(|e| e.render(text, source))
+}
+
+fn substitute_tokens(tokens: Vec<Token>, withs: &HashMap<&str, &str>, env_name: &str,
+                     allow_json: bool) -> Result<String, String> {
     let mut result = String::new();
     for token in tokens {
         match token {
             Token::Text(text) => {
                 result.push_str(text);
             },
-            Token::Substitute(choices) => {
-                let text = try!(substitute(&choices, &withs));
+            Token::Substitute(choices, filters) => {
+                let allow_json = allow_json || filters.contains(&Filter::Json);
+                let mut text = try!(substitute(&choices, &filters, &withs, env_name, allow_json));
+                for filter in filters.iter() {
+                    text = try!(filter.apply(&text));
+                }
                 result.push_str(&text);
             },
         }
@@ -65,12 +266,28 @@ pub fn untemplate(text: &str, withs: &HashMap<&str, &str>, shortcuts: bool
     Ok(result)
 }
 
-pub fn show_params(text: &str, use_shortcuts: bool) -> Result<String, String> {
-    let tokens = try!(Tokenizer::new(text, use_shortcuts).tokenize());
+/// Replace each substitution with the given placeholder, without looking up any values. This
+/// lets us parse a request file as yaml when we don't know what its parameters will be.
+pub fn stub_templates(text: &str, source: &str, placeholder: &str, shortcuts: bool
+                      ) -> Result<String, String> {
+    let tokens = try!(tokenize(text, source, shortcuts));
+    let mut result = String::new();
+    for token in tokens {
+        match token {
+            Token::Text(text) => { result.push_str(text); },
+            Token::Substitute(..) => { result.push_str(placeholder); },
+        }
+    }
+    Ok(result)
+}
+
+pub fn show_params(text: &str, source: &str, env_name: &str, use_shortcuts: bool
+                   ) -> Result<String, String> {
+    let tokens = try!(tokenize(text, source, use_shortcuts));
     let mut result = String::new();
     for token in tokens {
-        if let Token::Substitute(choices) = token {
-            let msg = try!(show_params_for_choices(&choices));
+        if let Token::Substitute(choices, filters) = token {
+            let msg = try!(show_params_for_choices(&choices, &filters, env_name));
             result.push_str(&msg);
         }
     }
@@ -81,10 +298,41 @@ pub fn show_params(text: &str, use_shortcuts: bool) -> Result<String, String> {
     }
 }
 
-pub fn show_params_for_choices<'a>(choices: &Vec<Choice<'a>>) -> Result<String, String> {
+/// Find the parameters in the text that have no value. Only lists with a --with key are included,
+/// since those are the only ones we can give a value to. Nothing is evaluated here, so a list
+/// only needs a value when none of its --with keys were given and it has nothing else to fall
+/// back on, like an environment key, a function or a default value.
+pub fn find_missing_params(text: &str, source: &str, withs: &HashMap<&str, &str>, env_name: &str,
+                           shortcuts: bool) -> Result<Vec<MissingParam>, String> {
+    let tokens = try!(tokenize(text, source, shortcuts));
+    let mut result: Vec<MissingParam> = Vec::new();
+    for token in tokens {
+        if let Token::Substitute(choices, filters) = token {
+            let names: Vec<&str> = choices.iter()
+                .filter_map(|c| if let &Choice::With(name) = c { Some(name) } else { None })
+                .collect();
+            if names.len() < choices.len() || names.iter().any(|name| withs.contains_key(name)) {
+                continue;
+            }
+            if let Some(name) = names.first() {
+                if result.iter().any(|p| p.name == *name) {
+                    continue;
+                }
+                result.push(MissingParam {
+                    name: name.to_string(),
+                    description: try!(show_params_for_choices(&choices, &filters, env_name)),
+                });
+            }
+        }
+    }
+    Ok(result)
+}
+
+pub fn show_params_for_choices<'a>(choices: &Vec<Choice<'a>>, filters: &Vec<Filter>, env_name: &str
+                                  ) -> Result<String, String> {
     let mut result = String::new();
     result.push_str(&format!("{} needs one of\n",
-                             try!(choices_to_string(choices))));
+                             try!(choices_to_string(choices, filters))));
     for choice in choices {
         result.push_str("    * ");
         match choice {
@@ -92,6 +340,7 @@ pub fn show_params_for_choices<'a>(choices: &Vec<Choice<'a>>) -> Result<String,
                 result.push_str(&format!("flag \"--with {} <value>\"", with));
             },
             &Choice::Env(name, ref key_path) => {
+                let name = if name.is_empty() { env_name } else { name };
                 let message =
                     if name.is_empty() {
                         format!("key {:?} from the active environment", key_path)
@@ -109,17 +358,29 @@ pub fn show_params_for_choices<'a>(choices: &Vec<Choice<'a>>) -> Result<String,
                     };
                 result.push_str(&message);
             },
+            &Choice::ProcessEnv(name) => {
+                result.push_str(&format!("environment variable \"{}\"", name));
+            },
+            &Choice::Function(name, ref args) => {
+                result.push_str(&format!("generated by {}({})", name, args.join(", ")));
+            },
             &Choice::DefaultVal(val) => {
                 result.push_str(&format!("defaults to \"{}\" if no matches are found", val));
             },
         }
         result.push_str("\n");
     }
+    if !filters.is_empty() {
+        let names: Vec<&str> = filters.iter().map(|f| f.name()).collect();
+        result.push_str(&format!("    and then applies filters {}\n", names.join(", ")));
+    }
     Ok(result)
 }
 
-fn substitute<'a>(choices: &Vec<Choice<'a>>, withs: &HashMap<&str, &str>
-                  ) -> Result<String, String> {
+/// Find the first choice that has a value. Json arrays and objects found in a response body are
+/// only substituted if allow_json is set.
+fn substitute<'a>(choices: &Vec<Choice<'a>>, filters: &Vec<Filter>, withs: &HashMap<&str, &str>,
+                  env_name: &str, allow_json: bool) -> Result<String, String> {
     for choice in choices {
         match choice {
             &Choice::With(with) => {
@@ -128,29 +389,64 @@ fn substitute<'a>(choices: &Vec<Choice<'a>>, withs: &HashMap<&str, &str>
                 }
             },
             &Choice::Env(name, ref key_path) => {
+                let name = if name.is_empty() { env_name } else { name };
                 if let Ok(y) = env::load_environment(name) {
-                    if let Some(&Yaml::String(ref val)) = yaml_util::get_nested_value(&y, key_path) {
-                        return Ok(val.to_string());
+                    if let Some(s) = find_env_value(&y, key_path, allow_json) {
+                        return Ok(s);
                     }
                 }
             },
             &Choice::Request(name, ref key_path) => {
                 let key_path: Vec<&str> = key_path.iter().map(|k| k.as_str()).collect();
-                let poo = remember::find_remembered_key(name, &key_path);
+                let poo = remember::find_remembered_key(name, &key_path, allow_json);
                 if let Ok(s) = poo {
                     return Ok(s.to_string());
                 }
             },
+            &Choice::ProcessEnv(name) => {
+                if let Ok(val) = std::env::var(name) {
+                    return Ok(val);
+                }
+            },
+            &Choice::Function(name, ref args) => {
+                let args: Vec<&str> = args.iter().map(|a| unquote(a)).collect();
+                return generate::call(name, &args);
+            },
             &Choice::DefaultVal(val) => {
                 return Ok(val.to_string());
             },
         }
     }
-    let s = try!(choices_to_string(choices));
+    let s = try!(choices_to_string(choices, filters));
     Err(format!("Failed to substitute for {}", s))
 }
 
-fn choices_to_string<'a>(choices: &Vec<Choice<'a>>) -> Result<String, String> {
+/// Grab a value from an environment. Key paths with wildcards, filters or array indices are
+/// looked up in the environment as json, the same way as in a request.
+fn find_env_value(y: &Yaml, key_path: &[&str], allow_json: bool) -> Option<String> {
+    let is_query = key_path.iter().any(|k| *k == "*" || k.starts_with("[?") || k.parse::<i64>().is_ok());
+    if is_query {
+        let found = yaml_util::yaml_to_json(y).and_then(|j| remember::json_find_path(&j, key_path).ok());
+        return match found {
+            None | Some(Json::Null) => None,
+            Some(ref j) if j.is_array() || j.is_object() => {
+                if allow_json { Some(j.to_string()) } else { None }
+            },
+            Some(ref j) => remember::json_to_string(j).ok(),
+        };
+    }
+    match yaml_util::get_nested_value(y, key_path) {
+        // a key with no value falls through to the next choice
+        None | Some(&Yaml::Null) => None,
+        Some(val) => {
+            yaml_util::scalar_to_string(val).or_else(|| {
+                if allow_json { yaml_util::yaml_to_json(val).map(|j| j.to_string()) } else { None }
+            })
+        },
+    }
+}
+
+fn choices_to_string<'a>(choices: &Vec<Choice<'a>>, filters: &Vec<Filter>) -> Result<String, String> {
     // build a sensible error message from the choices
     let mut result = String::from("{{");
     for choice in choices {
@@ -168,6 +464,9 @@ fn choices_to_string<'a>(choices: &Vec<Choice<'a>>) -> Result<String, String> {
         }
     }
     if result.ends_with(',') { result.pop(); }
+    for filter in filters.iter() {
+        result.push_str(&format!(" | {}", filter.name()));
+    }
     result.push_str(" }}");
     Ok(result)
 }
@@ -181,15 +480,21 @@ fn choice_to_string<'a>(choice: &Choice<'a>) -> Result<String, String> {
         &Choice::Env(name, ref key_path) => {
             result.push_str(&format!("[{}]", name));
             for key in key_path {
-                result.push_str(&format!(".{}", key));
+                result.push_str(&key_to_string(key));
             }
         },
         &Choice::Request(name, ref key_path) => {
             result.push_str(&format!("{}", name));
             for key in key_path {
-                result.push_str(&format!(".{}", key));
+                result.push_str(&key_to_string(key));
             }
         },
+        &Choice::ProcessEnv(name) => {
+            result.push_str(&format!("${}", name));
+        },
+        &Choice::Function(name, ref args) => {
+            result.push_str(&format!("{}({})", name, args.join(", ")));
+        },
         &Choice::DefaultVal(val) => {
             result.push_str(&format!("{}", val));
         },
@@ -197,11 +502,57 @@ fn choice_to_string<'a>(choice: &Choice<'a>) -> Result<String, String> {
     Ok(result)
 }
 
+/// Escape text so that untemplate() gives back the same text
+pub fn escape(text: &str, shortcuts: bool) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find("{{") {
+        // backslashes right before the braces are doubled, so they don't escape the braces
+        let before = &rest[..i];
+        let backslashes = before.len() - before.trim_right_matches('\\').len();
+        result.push_str(before);
+        for _ in 0..backslashes {
+            result.push('\\');
+        }
+        result.push_str("\\{{");
+        rest = &rest[i + 2..];
+    }
+    result.push_str(rest);
+    if shortcuts {
+        result.replace("@", "@@")
+    } else {
+        result
+    }
+}
+
+/// Filters like [?name=="foo"] follow the previous key, and other keys follow a '.'
+fn key_to_string(key: &str) -> String {
+    if key.starts_with("[?") {
+        key.to_string()
+    } else {
+        format!(".{}", key)
+    }
+}
+
+/// Remove the quotes around a string argument to a function, like "\"%Y\"" -> "%Y"
+fn unquote(arg: &str) -> &str {
+    if arg.len() >= 2 && (arg.starts_with('"') && arg.ends_with('"')
+                          || arg.starts_with('\'') && arg.ends_with('\'')) {
+        &arg[1..arg.len() - 1]
+    } else {
+        arg
+    }
+}
+
 
 pub struct Tokenizer<'a> {
     text: &'a str,
     char_indices: std::iter::Peekable<std::str::CharIndices<'a>>,
     shortcuts: bool,
+    // for error messages: where the current token started, and where the error was if it's
+    // not the current position
+    token_start: usize,
+    error_offset: Option<usize>,
 }
 
 impl<'a> Tokenizer<'a> {
@@ -210,16 +561,42 @@ impl<'a> Tokenizer<'a> {
             text: text,
             char_indices: text.char_indices().peekable(),
             shortcuts: shortcuts,
+            token_start: 0,
+            error_offset: None,
+        }
+    }
+
+    /// Split the text into tokens. An error includes the offset in the text where it was found.
+    /// That's the current position, except at eof where it's the start of the unfinished token.
+    pub fn tokenize(&mut self) -> Result<Vec<Token<'a>>, TemplateError> {
+        self.error_offset = None;
+        match self.read_tokens() {
+            Ok(tokens) => Ok(tokens),
+            Err(message) => {
+                let error_offset = self.error_offset;
+                let at_eof = self.eof();
+                let offset = match error_offset {
+                    Some(offset) => offset,
+                    None if at_eof => self.token_start,
+                    None => self.offset(),
+                };
+                Err(TemplateError { message: message, offset: offset })
+            },
         }
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token<'a>>, String> {
+    fn read_tokens(&mut self) -> Result<Vec<Token<'a>>, String> {
         // reset the iterator back to the start of the text
         self.char_indices = self.text.char_indices().peekable();
         let mut result: Vec<Token<'a>> = Vec::new();
         loop {
+            self.token_start = self.offset();
             if self.eof() {
                 break;
+            } else if self.backslashes_before_braces() > 0 {
+                result.extend(try!(self.read_backslashes()));
+            } else if self.shortcuts && self.has("@@") {
+                result.push(try!(self.read_escaped("@@")));
             } else if self.has("{{") {
                 result.push(try!(self.read_braces()));
             } else if self.shortcuts && self.has("@") {
@@ -242,7 +619,7 @@ impl<'a> Tokenizer<'a> {
                 end = self.text.len();
                 break;
             }
-            if self.has("{{") || (self.shortcuts && self.has("@")) {
+            if self.has("{{") || self.backslashes_before_braces() > 0 || (self.shortcuts && self.has("@")) {
                 let &(offset, _) = self.peek().unwrap();
                 end = offset;
                 break;
@@ -256,11 +633,54 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
+    /// The number of backslashes at the current position, if they're right before a "{{"
+    fn backslashes_before_braces(&mut self) -> usize {
+        let offset = self.offset();
+        let rest = &self.text[offset..];
+        let n = rest.chars().take_while(|&c| c == '\\').count();
+        if n > 0 && rest[n..].starts_with("{{") { n } else { 0 }
+    }
+
+    /// Read the backslashes before a "{{". Each pair is one literal backslash, and an odd one
+    /// out escapes the braces. So \{{ is a literal "{{", \\{{ a }} is a backslash and then a
+    /// template, and \\\{{ is a backslash and then a literal "{{".
+    fn read_backslashes(&mut self) -> Result<Vec<Token<'a>>, String> {
+        let n = self.backslashes_before_braces();
+        if n == 0 { panic!("BUG: read_backslashes called when no backslashes found"); }
+        let start = self.offset();
+        for _ in 0..n {
+            self.next();
+        }
+        let mut result: Vec<Token<'a>> = Vec::new();
+        if n / 2 > 0 {
+            result.push(Token::Text(&self.text[start..start + n / 2]));
+        }
+        if n % 2 == 1 {
+            let braces = self.offset();
+            self.next(); self.next();
+            result.push(Token::Text(&self.text[braces..braces + 2]));
+        }
+        Ok(result)
+    }
+
+    /// Read an escape sequence, like "@@", and return the literal text without the escape
+    /// character
+    fn read_escaped(&mut self, escape: &'static str) -> Result<Token<'a>, String> {
+        if !self.has(escape) { panic!("BUG: read_escaped called when no escape found"); }
+        let &(start, _) = self.peek().unwrap();
+        for _ in escape.chars() {
+            self.next();
+        }
+        Ok(Token::Text(&self.text[start + 1..start + escape.len()]))
+    }
+
     /// Read a list of items delimited by double braces, like {{<item>, <item>, ...}}
+    /// The list may end with filters, like {{<item>, <item> | <filter> | <filter>}}
     fn read_braces(&mut self) -> Result<Token<'a>, String> {
         if !self.has("{{") { panic!("BUG: read_braces called when no braces found"); }
         self.next(); self.next();
         let mut result: Vec<Choice<'a>> = Vec::new();
+        let mut filters: Vec<Filter> = Vec::new();
         loop {
             let token = try!(self.read_brace_item());
             result.push(token);
@@ -275,10 +695,15 @@ impl<'a> Tokenizer<'a> {
             } else if self.has(":") {
                 self.next();
                 let default = try!(self.read_default_value()).trim();
-                if !self.has("}}") {
+                if !self.has("}}") && !self.has("|") {
                     return Err("Default value must be the last list item after the ':'.".to_string());
                 }
                 result.push(Choice::DefaultVal(default));
+                filters = try!(self.read_filters());
+                self.next(); self.next();
+                break;
+            } else if self.has("|") {
+                filters = try!(self.read_filters());
                 self.next(); self.next();
                 break;
             }
@@ -286,20 +711,49 @@ impl<'a> Tokenizer<'a> {
         if result.is_empty() {
             Err("Found empty braces".to_string())
         } else {
-            Ok(Token::Substitute(result))
+            Ok(Token::Substitute(result, filters))
+        }
+    }
+
+    /// Read filters like "| <filter> | <filter>" up to, but not including, the closing "}}"
+    fn read_filters(&mut self) -> Result<Vec<Filter>, String> {
+        let mut filters: Vec<Filter> = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.has("}}") {
+                break;
+            } else if self.eof() {
+                return Err("Unclosed braces".to_string());
+            }
+            try!(self.expect_char('|'));
+            self.skip_whitespace();
+            let start = self.offset();
+            let name = try!(self.read_item_name());
+            match Filter::from_name(name) {
+                Ok(filter) => { filters.push(filter); },
+                Err(e) => {
+                    self.error_offset = Some(start);
+                    return Err(e);
+                },
+            }
         }
+        Ok(filters)
     }
 
     /// Read one of:
     ///     - A --with key like <name>
+    ///     - A function call like "uuid()" or "random_int(1, 100)"
     ///     - A request lookup like "last.response.body.id"
     ///     - An environment lookup like "[env].thing.id"
+    ///     - A process environment variable lookup like "$THING"
     fn read_brace_item(&mut self) -> Result<Choice<'a>, String> {
         self.skip_whitespace();
         if self.eof() {
             return Err("Expected a template list item, but found eof".to_string());
         } else if self.has("[") {
             self.read_env_item()
+        } else if self.has("$") {
+            self.read_process_env_item()
         } else {
             self.read_with_or_request_item()
         }
@@ -326,7 +780,7 @@ impl<'a> Tokenizer<'a> {
                 },
                 choice => choice,
             };
-        Ok(Token::Substitute(vec![choice]))
+        Ok(Token::Substitute(vec![choice], Vec::new()))
     }
 
     /// Read something like [<name>].<key>.<key>
@@ -356,12 +810,28 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
+    /// Read something like $<name>
+    fn read_process_env_item(&mut self) -> Result<Choice<'a>, String> {
+        try!(self.expect_char('$'));
+        let name = try!(self.read_item_name());
+        Ok(Choice::ProcessEnv(name))
+    }
+
     /// Read a --with key name that looks like <name> -- returns a Choice::With
     /// Or read a request name + key_path, like "last.response.body.id" -- returns a Choice::Request
+    /// Or read a function call, like "random_int(1, 100)" -- returns a Choice::Function
     fn read_with_or_request_item(&mut self) -> Result<Choice<'a>, String> {
         assert!(!self.eof());
+        let start = self.offset();
         let name = try!(self.read_item_name());
-        if self.has(".") {
+        if self.has("(") {
+            let args = try!(self.read_function_args());
+            if let Err(e) = generate::check(name, &args) {
+                self.error_offset = Some(start);
+                return Err(e);
+            }
+            Ok(Choice::Function(name, args))
+        } else if self.has(".") {
             let key_path = try!(self.read_key_path());
             if key_path.is_empty() {
                 Err(format!("Expected key after \"{}.\"", name))
@@ -374,7 +844,58 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
-    /// Read a key path, starting with a '.', like ".response.body.id"
+    /// Read the arguments to a function, like ("%Y-%m-%d") or (1, 100). Each argument is a
+    /// quoted string or a bare value like a number. Quoted arguments keep their quotes.
+    fn read_function_args(&mut self) -> Result<Vec<&'a str>, String> {
+        try!(self.expect_char('('));
+        let mut args: Vec<&'a str> = Vec::new();
+        self.skip_whitespace();
+        if self.has(")") {
+            self.next();
+            return Ok(args);
+        }
+        loop {
+            self.skip_whitespace();
+            let arg =
+                if self.has("\"") || self.has("'") {
+                    try!(self.read_quoted_string())
+                } else {
+                    try!(self.read_item_name())
+                };
+            args.push(arg);
+            self.skip_whitespace();
+            if self.has(",") {
+                self.next();
+            } else if self.has(")") {
+                self.next();
+                break;
+            } else if self.eof() {
+                return Err("Found eof while reading function arguments. Expected ')'".to_string());
+            } else {
+                let c = self.peek_char().unwrap();
+                return Err(format!("Expected ',' or ')' after a function argument, but found '{}'", c));
+            }
+        }
+        Ok(args)
+    }
+
+    /// Read a string in single or double quotes, and return it with the quotes
+    fn read_quoted_string(&mut self) -> Result<&'a str, String> {
+        let (start, quote) = *self.peek().unwrap();
+        self.next();
+        loop {
+            match self.next() {
+                Some((offset, c)) if c == quote => {
+                    return Ok(&self.text[start..offset + 1]);
+                },
+                Some(_) => {},
+                None => { return Err(format!("Unclosed quote {:?} in function argument", quote)); },
+            }
+        }
+    }
+
+    /// Read a key path, starting with a '.', like ".response.body.id". A key may also be a
+    /// wildcard, like ".things.*.id", or be followed by a filter, like ".things[?name==\"foo\"].id"
     fn read_key_path(&mut self) -> Result<Vec<&'a str>, String> {
         let mut key_path: Vec<&'a str> = Vec::new();
         loop {
@@ -384,13 +905,40 @@ impl<'a> Tokenizer<'a> {
             } else {
                 self.next();
             }
-            let part = try!(self.read_item_name());
-            key_path.push(part);
+            if self.has("*") {
+                let &(start, _) = self.peek().unwrap();
+                self.next();
+                key_path.push(&self.text[start..start + 1]);
+            } else {
+                let part = try!(self.read_item_name());
+                key_path.push(part);
+            }
+            if self.has("[?") {
+                key_path.push(try!(self.read_filter()));
+            }
         }
         Ok(key_path)
     }
 
-    /// Return the text between the ':' and closing "}}" in a list.
+    /// Read a filter like [?name=="foo"], including the brackets
+    fn read_filter(&mut self) -> Result<&'a str, String> {
+        let &(start, _) = self.peek().unwrap();
+        let mut quote: Option<char> = None;
+        loop {
+            match self.next() {
+                Some((offset, ']')) if quote.is_none() => {
+                    return Ok(&self.text[start..offset + 1]);
+                },
+                Some((_, c)) if Some(c) == quote => { quote = None; },
+                Some((_, c)) if quote.is_none() && (c == '"' || c == '\'') => { quote = Some(c); },
+                Some(_) => {},
+                None => { return Err("Found eof while reading a filter. Expected ']'".to_string()); },
+            }
+        }
+    }
+
+    /// Return the text between the ':' and the closing "}}" or first filter '|' in a list. A '|'
+    /// inside quotes is part of the default value, like {{ sep: "a|b" }}.
     /// Assumes the ':' has already been consumed.
     fn read_default_value(&mut self) -> Result<&'a str, String> {
         self.skip_whitespace();
@@ -399,16 +947,22 @@ impl<'a> Tokenizer<'a> {
         }
         let &(start, _) = self.peek().unwrap();
         let end;
+        let mut quote: Option<char> = None;
         loop {
             if self.eof() {
                 return Err("Found eof while reading default value. Unclosed braces".to_string());
             }
-            if self.has("}}") {
+            if self.has("}}") || (quote.is_none() && self.has("|")) {
                 let &(offset, _) = self.peek().unwrap();
                 end = offset;
                 break;
             }
-            self.next();
+            let (_, c) = self.next().unwrap();
+            match quote {
+                Some(q) if c == q => { quote = None; },
+                None if c == '"' || c == '\'' => { quote = Some(c); },
+                _ => {},
+            }
         }
         if start == end {
             Err("Found empty default value.".to_string())
@@ -463,6 +1017,14 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
+    /// Return the byte offset of the current position
+    fn offset(&mut self) -> usize {
+        match self.peek() {
+            Some(&(offset, _)) => offset,
+            None => self.text.len(),
+        }
+    }
+
     /// Check for the end of the text
     fn eof(&mut self) -> bool {
         return self.peek().is_none()
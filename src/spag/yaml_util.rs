@@ -3,7 +3,10 @@ use yaml_rust::Yaml;
 use yaml_rust::yaml::Hash;
 use yaml_rust::YamlEmitter;
 use yaml_rust::YamlLoader;
+use std::collections::BTreeMap;
+
 use rustc_serialize::json;
+use rustc_serialize::json::Json;
 
 use super::file;
 
@@ -52,26 +55,130 @@ pub fn get_value_as_string(y: &Yaml, keys: &[&str]) -> Result<String, String> {
     }
 }
 
+/// Returns the string form of a scalar yaml value, or None for arrays, maps, and bad values.
+/// A null value (like "key:" with nothing after it) is an empty string.
+pub fn scalar_to_string(y: &Yaml) -> Option<String> {
+    match *y {
+        Yaml::String(ref s) => Some(s.to_string()),
+        Yaml::Integer(i) => Some(format!("{}", i)),
+        Yaml::Real(ref s) => Some(s.to_string()),
+        Yaml::Boolean(b) => Some(format!("{}", b)),
+        Yaml::Null => Some(String::new()),
+        _ => None,
+    }
+}
+
+/// Show a yaml value in a message: a scalar as plain text, and a list or map as json
+pub fn show_yaml(y: &Yaml) -> String {
+    scalar_to_string(y)
+        .or_else(|| yaml_to_json(y).map(|j| j.to_string()))
+        .unwrap_or(format!("{:?}", y))
+}
+
+/// Convert yaml to json, like for substituting a list or map into a request body. Map keys are
+/// stringified. Returns None if there's a bad value anywhere inside.
+pub fn yaml_to_json(y: &Yaml) -> Option<Json> {
+    match *y {
+        Yaml::String(ref s) => Some(Json::String(s.to_string())),
+        Yaml::Integer(i) => Some(Json::I64(i)),
+        Yaml::Real(ref s) => s.parse::<f64>().ok().map(Json::F64),
+        Yaml::Boolean(b) => Some(Json::Boolean(b)),
+        Yaml::Null => Some(Json::Null),
+        Yaml::Array(ref arr) => {
+            let items: Option<Vec<Json>> = arr.iter().map(yaml_to_json).collect();
+            items.map(Json::Array)
+        },
+        Yaml::Hash(ref h) => {
+            let mut result = BTreeMap::new();
+            for (k, v) in h.iter() {
+                match (scalar_to_string(k), yaml_to_json(v)) {
+                    (Some(k), Some(v)) => { result.insert(k, v); },
+                    _ => { return None; },
+                }
+            }
+            Some(Json::Object(result))
+        },
+        _ => None,
+    }
+}
+
+/// Parse a value from the command line as the given type. The types are str (the default), int,
+/// float, bool, or yaml for any yaml literal, like "[1, 2]" or "{a: b}".
+pub fn parse_typed_value(val: &str, type_name: &str) -> Result<Yaml, String> {
+    match type_name {
+        "" | "str" => Ok(Yaml::String(val.to_string())),
+        "int" => {
+            val.trim().parse::<i64>().map(Yaml::Integer)
+                .map_err(|_| format!("Invalid int value {:?}", val))
+        },
+        "float" => {
+            val.trim().parse::<f64>().map(|_| Yaml::Real(val.trim().to_string()))
+                .map_err(|_| format!("Invalid float value {:?}", val))
+        },
+        "bool" => {
+            match val.trim() {
+                "true" => Ok(Yaml::Boolean(true)),
+                "false" => Ok(Yaml::Boolean(false)),
+                _ => Err(format!("Invalid bool value {:?}. Expected true or false", val)),
+            }
+        },
+        "yaml" => {
+            match YamlLoader::load_from_str(val) {
+                Ok(mut docs) => Ok(if docs.is_empty() { Yaml::Null } else { docs.remove(0) }),
+                Err(e) => Err(format!("Invalid yaml value {:?} -- {:?}", val, e)),
+            }
+        },
+        _ => Err(format!("Unknown value type {:?}. Expected one of str, int, float, bool or yaml",
+                         type_name)),
+    }
+}
+
 /// If keys is ["a", "b", "c"], then set y["a"]["b"]["c"] = <val>. This will create all of the
-/// intermediate maps if they don't exist.
-pub fn set_nested_value(y: &mut Yaml, keys: &[&str], val: &str) {
+/// intermediate maps if they don't exist. It fails if one of them is already set to something
+/// other than a map.
+pub fn set_nested_value(y: &mut Yaml, keys: &[&str], val: &str) -> Result<(), String> {
+    set_nested_yaml(y, keys, Yaml::String(val.to_string()))
+}
+
+/// Like set_nested_value(), but for any yaml value
+pub fn set_nested_yaml(y: &mut Yaml, keys: &[&str], val: Yaml) -> Result<(), String> {
     if keys.is_empty() {
         panic!("BUG: No keys given to set in the environment.");
     }
-    let key = Yaml::String(keys[0].to_string());
+    set_nested_yaml_at(y, keys, 0, val)
+}
+
+fn set_nested_yaml_at(y: &mut Yaml, keys: &[&str], depth: usize, val: Yaml) -> Result<(), String> {
     if let Yaml::Hash(ref mut h) = *y {
-        if keys.len() == 1 {
-            h.insert(key, Yaml::String(val.to_string()));
-        } else {
-            // create nested dictionaries if they don't exist
-            if let None = h.get_mut(&key) {
-                h.insert(key.clone(), Yaml::Hash(yaml_rust::yaml::Hash::new()));
+        let key = Yaml::String(keys[depth].to_string());
+        if depth + 1 == keys.len() {
+            h.insert(key, val);
+            return Ok(());
+        }
+        // create nested dictionaries if they don't exist
+        if let None = h.get_mut(&key) {
+            h.insert(key.clone(), Yaml::Hash(yaml_rust::yaml::Hash::new()));
+        }
+        return set_nested_yaml_at(h.get_mut(&key).unwrap(), keys, depth + 1, val);
+    }
+    let parent = if depth == 0 { "the top level".to_string() } else { format!("{:?}", keys[..depth].join(".")) };
+    Err(format!("Failed to set {:?} because {} is not a map", keys.join("."), parent))
+}
+
+/// Deep merge overrides into base. Maps are merged key by key, and anything else in overrides
+/// replaces what's in base.
+pub fn merge(base: &mut Yaml, overrides: &Yaml) {
+    if let (&mut Yaml::Hash(ref mut base_hash), &Yaml::Hash(ref override_hash)) = (&mut *base, overrides) {
+        for (k, v) in override_hash.iter() {
+            if let Some(base_value) = base_hash.get_mut(k) {
+                merge(base_value, v);
+                continue;
             }
-            set_nested_value(h.get_mut(&key).unwrap(), &keys[1..], val);
+            base_hash.insert(k.clone(), v.clone());
         }
-    } else {
-        panic!(format!("Failed to set key {:?} in {:?}", key, y));
+        return;
     }
+    *base = overrides.clone();
 }
 
 /// If keys is ["a", "b", "c"], then unset y["a"]["b"]["c"]
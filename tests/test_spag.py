@@ -13,10 +13,16 @@ print "Using spag at %s" % SPAG_PROG
 ENDPOINT = 'http://localhost:5000'
 RESOURCES_DIR = os.path.join(os.path.dirname(__file__), 'resources')
 TEMPLATES_DIR = os.path.join(os.path.dirname(__file__), 'templates')
+SUITES_DIR = os.path.join(os.path.dirname(__file__), 'suites')
+LINT_DIR = os.path.join(os.path.dirname(__file__), 'lint')
+DOTENV_DIR = os.path.join(os.path.dirname(__file__), 'dotenv')
 V1_RESOURCES_DIR = os.path.join(RESOURCES_DIR, 'v1')
 V2_RESOURCES_DIR = os.path.join(RESOURCES_DIR, 'v2')
 SPAG_REMEMBERS_DIR = '.spag/remembers'
 SPAG_HISTORY_FILE = '.spag/history.yml'
+# global environments go here instead of ~/.config/spag while testing
+CONFIG_HOME = os.path.abspath(os.path.join(os.path.dirname(__file__), 'config_home'))
+GLOBAL_ENV_DIR = os.path.join(CONFIG_HOME, 'spag', 'environments')
 
 def rm_dir(dirname):
     try:
@@ -31,16 +37,27 @@ def rm_file(filename):
     except OSError:
         pass
 
-def run_spag(*args):
+def run_spag(*args, **kwargs):
     """
+    :param env: Extra environment variables to run spag with
+    :param input: Text to send to spag on stdin
+    :param cwd: The directory to run spag in
     :returns: A tuple (out, err, ret) where
         out is the output on stdout
         err is the output on stderr
         ret is the exit code
     """
-    cmd = [SPAG_PROG] + list(args)
-    p = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
-    out, err = p.communicate()
+    prog = SPAG_PROG
+    if 'cwd' in kwargs and os.sep in prog:
+        prog = os.path.abspath(prog)
+    cmd = [prog] + list(args)
+    env = dict(os.environ)
+    env['XDG_CONFIG_HOME'] = CONFIG_HOME
+    env.update(kwargs.get('env', {}))
+    # never give spag our terminal, so it can't wait on us for input
+    p = subprocess.Popen(cmd, stdin=subprocess.PIPE, stdout=subprocess.PIPE,
+                         stderr=subprocess.PIPE, env=env, cwd=kwargs.get('cwd'))
+    out, err = p.communicate(kwargs.get('input', '').encode('utf-8'))
     return (out.decode('utf-8'), err.decode('utf-8'), p.returncode)
 
 
@@ -55,10 +72,12 @@ class BaseTest(unittest.TestCase):
         run_spag('env', 'unset', '--everything')
         rm_dir(SPAG_REMEMBERS_DIR)
         rm_file(SPAG_HISTORY_FILE)
+        rm_dir(CONFIG_HOME)
 
     def tearDown(self):
         rm_dir(SPAG_REMEMBERS_DIR)
         rm_file(SPAG_HISTORY_FILE)
+        rm_dir(CONFIG_HOME)
         super(BaseTest, self).tearDown()
 
 
@@ -105,6 +124,16 @@ class TestHeaders(BaseTest):
         self.assertEqual(ret, 0)
         self.assertEqual(json.loads(out).get('A'), 'c')
 
+    def test_typed_environment_headers(self):
+        run_spag('env', 'set', 'headers.X-Count', '5', '--type', 'int')
+        run_spag('env', 'set', 'headers.X-Flag', 'true', '--type', 'bool')
+
+        out, err, ret = run_spag('get', '/headers', '-e', ENDPOINT)
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out).get('X-Count'), '5')
+        self.assertEqual(json.loads(out).get('X-Flag'), 'true')
+
 class TestParams(BaseTest):
 
     def test_one_request_param(self):
@@ -143,6 +172,38 @@ class TestParams(BaseTest):
         self.assertEqual(json.loads(out), {'foo': 'bar',
                                            'bar': ''})
 
+    def test_param_flag(self):
+        out, err, ret = run_spag('get', '/params/all', '-e', ENDPOINT,
+                                 '-q', 'foo=bar', '--param', 'a b=c&d=e')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {'foo': ['bar'],
+                                           'a b': ['c&d=e']})
+
+    def test_param_flag_merged_with_uri_query(self):
+        out, err, ret = run_spag('get', '/params/all?foo=bar', '-e', ENDPOINT,
+                                 '-q', 'foo=baz', '-q', 'x=y')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {'foo': ['bar', 'baz'],
+                                           'x': ['y']})
+
+        out, err, ret = run_spag('history')
+        self.assertEqual(out, '0: GET %s/params/all?foo=bar&foo=baz&x=y\n' % ENDPOINT)
+
+    def test_param_flag_substitution(self):
+        out, err, ret = run_spag('get', '/auth', '-e', ENDPOINT)
+        self.assertEqual(ret, 0)
+        out, err, ret = run_spag('get', '/params/all', '-e', ENDPOINT, '-q', 'token=@token')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {'token': ['abcde']})
+
+    def test_invalid_param_flag(self):
+        out, err, ret = run_spag('get', '/params/all', '-e', ENDPOINT, '-q', 'poo')
+        self.assertNotEqual(ret, 0)
+        self.assertEqual(err, 'Invalid query parameter "poo"\n')
+
 class TestGet(BaseTest):
 
     def test_get_no_endpoint(self):
@@ -262,6 +323,52 @@ class TestDelete(BaseTest):
         self.assertEqual(ret, 0)
         self.assertEqual(json.loads(out), {"things": []})
 
+class TestHead(BaseTest):
+
+    def test_spag_head(self):
+        out, err, ret = run_spag('head', '/things', '-e', ENDPOINT)
+        self.assertEqual(err, '')
+        self.assertEqual(out, '\n')
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('history')
+        self.assertEqual(out, '0: HEAD %s/things\n' % ENDPOINT)
+
+class TestOptions(BaseTest):
+
+    def test_spag_options(self):
+        out, err, ret = run_spag('options', '/things', '-e', ENDPOINT)
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('history')
+        self.assertEqual(out, '0: OPTIONS %s/things\n' % ENDPOINT)
+
+class TestSend(BaseTest):
+
+    def test_spag_send(self):
+        out, err, ret = run_spag('send', 'get', '/things', '-e', ENDPOINT)
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {"things": []})
+        self.assertEqual(ret, 0)
+
+    def test_spag_send_custom_method(self):
+        out, err, ret = run_spag('send', 'purge', '/things/a', '-e', ENDPOINT,
+                                 '-H', 'X-Purge-Key: abc', '--data', 'all')
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {"method": "PURGE", "id": "a", "key": "abc", "data": "all"})
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('history')
+        self.assertEqual(out, '0: PURGE %s/things/a\n' % ENDPOINT)
+
+    def test_spag_send_invalid_method(self):
+        out, err, ret = run_spag('send', 'GET /things', '/things', '-e', ENDPOINT)
+        self.assertEqual(err, 'Invalid http method "GET /things". A method may only have letters, '
+                              "digits and any of !#$%&'*+-.^_`|~\n")
+        self.assertEqual(out, '')
+        self.assertEqual(ret, 1)
+
 
 class TestSpagFiles(BaseTest):
 
@@ -313,6 +420,13 @@ class TestSpagFiles(BaseTest):
         self.assertEqual(err, '')
         self.assertEqual(ret, 0)
 
+    def test_spag_request_params(self):
+        out, err, ret = run_spag('request', 'params.yml', '-q', 'limit=20')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {"limit": ["10", "20"],
+                                           "tag": ["a", "b/c d"]})
+
     def test_spag_request_headers_override(self):
         out, err, ret = run_spag('request', 'headers.yml',
                                  '-H', 'Hello:abcde')
@@ -344,6 +458,7 @@ class TestSpagFiles(BaseTest):
             tests/resources/auth.yml
             tests/resources/delete_thing.yml
             tests/resources/headers.yml
+            tests/resources/params.yml
             tests/resources/v1/get_thing.yml
             tests/resources/v1/post_thing.yml
             tests/resources/v2/get_thing.yml
@@ -354,6 +469,61 @@ class TestSpagFiles(BaseTest):
         self.assertEqual(parse(out), parse(expected))
         self.assertEqual(ret, 0)
 
+    def test_spag_request_w_bad_method(self):
+        out, err, ret = run_spag('request', 'bad_method', '--dir', LINT_DIR)
+        self.assertTrue(err.startswith('Invalid http method "GET /things"'))
+        self.assertEqual(out, '')
+        self.assertEqual(ret, 1)
+
+    def test_spag_request_lint(self):
+        out, err, ret = run_spag('request', 'lint', '--dir', os.path.join(LINT_DIR, 'good'))
+        self.assertEqual(err, '')
+        self.assertEqual(out, '')
+        self.assertEqual(ret, 0)
+
+    def test_spag_request_lint_w_problems(self):
+        out, err, ret = run_spag('request', 'lint', '--dir', os.path.abspath(LINT_DIR))
+        self.assertEqual(out, '')
+        self.assertEqual(ret, 1)
+        lines = err.splitlines()
+        self.assertTrue(lines[0].startswith(
+            'tests/lint/bad_method.yml:2: Invalid http method "GET /things"'))
+        self.assertTrue(lines[1].startswith('tests/lint/bad_yaml.yml: Invalid yaml -- '))
+        self.assertEqual(lines[2], 'tests/lint/missing_uri.yml: Missing required key "uri"')
+        self.assertTrue(lines[3].startswith(
+            'tests/lint/unknown_filter.yml:4:27: Unknown template filter "wumbo"'))
+        self.assertEqual(lines[4:], [
+            '    {"id": "{{ thing_id | wumbo }}"}',
+            ' ' * 26 + '^',
+            'tests/lint/warning/unknown_method.yml:1: Warning: "GTE" isn\'t a standard http method',
+            'Found 4 problem(s) in request files',
+        ])
+
+    def test_spag_request_lint_warnings_pass(self):
+        out, err, ret = run_spag('request', 'lint', '--dir', os.path.join(LINT_DIR, 'warning'))
+        self.assertEqual(err, 'tests/lint/warning/unknown_method.yml:1: Warning: '
+                              '"GTE" isn\'t a standard http method\n')
+        self.assertEqual(out, '')
+        self.assertEqual(ret, 0)
+
+    def test_spag_request_as_curl(self):
+        out, err, ret = run_spag('request', 'v2/post_thing.yml', '--as-curl')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out, textwrap.dedent("""\
+            curl -X POST '{0}/things' \\
+              -H 'Accept: application/json' \\
+              -H 'Content-Type: application/json' \\
+              --data-raw '{{
+                "id": "c"
+            }}
+            '
+            """).format(ENDPOINT))
+
+        # nothing was sent
+        out, err, ret = run_spag('history')
+        self.assertEqual(out, '')
+
     def test_spag_cat_request(self):
         out, err, ret = run_spag('request', 'cat', 'auth.yml')
         self.assertEqual(err, '')
@@ -443,17 +613,543 @@ class TestSpagEnvironments(BaseTest):
         touch(".spag/environments/1.yml")
         touch(".spag/environments/2.yml")
         touch(".spag/environments/3.yml")
+        os.makedirs(GLOBAL_ENV_DIR)
+        touch(os.path.join(GLOBAL_ENV_DIR, "corp.yml"))
 
         out, err, ret = run_spag('env', 'ls')
         self.assertEqual(err, '')
         self.assertEqual(ret, 0)
         self.assertEqual(out.strip(),
             textwrap.dedent("""
-            .spag/environments/1.yml
-            .spag/environments/2.yml
-            .spag/environments/3.yml
-            .spag/environments/default.yml
-            """).strip())
+            project .spag/environments/1.yml
+            project .spag/environments/2.yml
+            project .spag/environments/3.yml
+            project .spag/environments/default.yml
+            global  {0}/corp.yml
+            """).strip().format(GLOBAL_ENV_DIR))
+
+    def test_spag_env_set_global(self):
+        out, err, ret = run_spag('env', 'set', 'proxy', 'http://proxy:3128',
+                                 'endpoint', 'http://global:5000', '--global')
+        self.assertEqual(err, '')
+        self.assertIn('"proxy": "http://proxy:3128"', out)
+        self.assertEqual(ret, 0)
+        self.assertTrue(os.path.exists(os.path.join(GLOBAL_ENV_DIR, 'default.yml')))
+
+        # project values are layered on top of the global ones
+        out, err, ret = run_spag('env', 'set', 'endpoint', ENDPOINT)
+        self.assertNotIn('proxy', out)
+        out, err, ret = run_spag('env', 'cat', '--resolved')
+        self.assertEqual(err, '')
+        self.assertIn('"proxy": "http://proxy:3128"', out)
+        self.assertIn('"endpoint": "%s"' % ENDPOINT, out)
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('get', '/auth')
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {"token": "abcde"})
+
+    def test_spag_env_global_only(self):
+        os.makedirs(GLOBAL_ENV_DIR)
+        with open(os.path.join(GLOBAL_ENV_DIR, 'corp.yml'), 'w') as f:
+            f.write('endpoint: %s\n' % ENDPOINT)
+
+        out, err, ret = run_spag('env', 'activate', 'corp')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.addCleanup(run_spag, 'env', 'deactivate')
+
+        out, err, ret = run_spag('get', '/auth')
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {"token": "abcde"})
+
+    def test_spag_env_extends(self):
+        def write_env(name, contents):
+            filename = ".spag/environments/%s.yml" % name
+            with open(filename, 'w') as f:
+                f.write(textwrap.dedent(contents))
+            self.addCleanup(rm_file, filename)
+
+        write_env('base', """
+            endpoint: http://localhost:9999
+            headers:
+                Accept: application/json
+                mini: base
+            """)
+        write_env('staging', """
+            extends: base
+            headers:
+                mini: staging
+            """)
+        write_env('mystaging', """
+            extends: staging
+            endpoint: %s
+            """ % ENDPOINT)
+
+        out, err, ret = run_spag('env', 'cat', 'mystaging', '--resolved')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(yaml.load(out), {
+            'endpoint': ENDPOINT,
+            'headers': {'Accept': 'application/json', 'mini': 'staging'},
+        })
+
+        # without --resolved, show the file as it is
+        out, err, ret = run_spag('env', 'cat', 'mystaging')
+        self.assertEqual(yaml.load(out), {'extends': 'staging', 'endpoint': ENDPOINT})
+
+        run_spag('env', 'activate', 'mystaging')
+        self.addCleanup(run_spag, 'env', 'deactivate')
+        out, err, ret = run_spag('get', '/headers')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {'Mini': 'staging'})
+
+    def test_spag_env_extends_errors(self):
+        def write_env(name, contents):
+            filename = ".spag/environments/%s.yml" % name
+            with open(filename, 'w') as f:
+                f.write(contents)
+            self.addCleanup(rm_file, filename)
+
+        write_env('loop1', 'extends: loop2')
+        write_env('loop2', 'extends: loop1')
+        write_env('orphan', 'extends: ninnymuggins')
+
+        out, err, ret = run_spag('env', 'cat', 'loop1', '--resolved')
+        self.assertEqual(err,
+            'Found a cycle of environments extending each other: '
+            '.spag/environments/loop1.yml -> .spag/environments/loop2.yml -> '
+            '.spag/environments/loop1.yml\n')
+        self.assertEqual(ret, 1)
+
+        out, err, ret = run_spag('env', 'cat', 'orphan', '--resolved')
+        self.assertEqual(err,
+            'Failed to load environment "ninnymuggins" extended by '
+            '.spag/environments/orphan.yml -- Environment not found\n')
+        self.assertEqual(ret, 1)
+
+        # the file itself can still be shown
+        out, err, ret = run_spag('env', 'cat', 'orphan')
+        self.assertEqual((out, err, ret), ('extends: ninnymuggins\n', '', 0))
+
+    def test_spag_env_copy_rename_rm(self):
+        self.addCleanup(rm_file, '.spag/environments/staging.yml')
+        self.addCleanup(rm_file, '.spag/environments/prod.yml')
+        self.addCleanup(rm_file, '.spag/environments/child.yml')
+
+        out, err, ret = run_spag('env', 'copy', 'default', 'staging')
+        self.assertEqual(err, '')
+        self.assertIn('"endpoint": "%s"' % ENDPOINT, out)
+        self.assertEqual(ret, 0)
+
+        with open('.spag/environments/child.yml', 'w') as f:
+            f.write('extends: staging\n')
+
+        out, err, ret = run_spag('env', 'copy', 'default', 'staging')
+        self.assertEqual(err, 'Environment "staging" already exists\n')
+        self.assertEqual(ret, 1)
+
+        out, err, ret = run_spag('env', 'activate', 'staging')
+        self.assertEqual(ret, 0)
+        self.addCleanup(run_spag, 'env', 'deactivate')
+
+        # the active environment follows the rename
+        out, err, ret = run_spag('env', 'rename', 'staging', 'prod')
+        self.assertEqual(err, '')
+        self.assertIn('"endpoint": "%s"' % ENDPOINT, out)
+        self.assertEqual(ret, 0)
+        self.assertFalse(os.path.exists('.spag/environments/staging.yml'))
+        self.assertTrue(os.path.exists('.spag/environments/prod.yml'))
+        with open('.spag/environments/active') as f:
+            self.assertEqual(f.read(), 'prod')
+
+        # environments extending it follow the rename too
+        out, err, ret = run_spag('env', 'cat', 'child', '--resolved')
+        self.assertEqual(err, '')
+        self.assertIn('"endpoint": "%s"' % ENDPOINT, out)
+        self.assertEqual(ret, 0)
+        out, err, ret = run_spag('env', 'cat', 'child')
+        self.assertIn('prod', out)
+        self.assertNotIn('staging', out)
+
+        out, err, ret = run_spag('env', 'rm', 'prod')
+        self.assertEqual(err, 'Refusing to remove the active environment "prod". '
+                              'Use --force to remove it anyway.\n')
+        self.assertEqual(ret, 1)
+        self.assertTrue(os.path.exists('.spag/environments/prod.yml'))
+
+        out, err, ret = run_spag('env', 'rm', 'prod', '--force')
+        self.assertEqual((out, err, ret), ('', '', 0))
+        self.assertFalse(os.path.exists('.spag/environments/prod.yml'))
+        with open('.spag/environments/active') as f:
+            self.assertEqual(f.read(), 'default')
+
+    def test_spag_env_rm_selected(self):
+        self.addCleanup(rm_file, '.spag/environments/staging.yml')
+        run_spag('env', 'copy', 'default', 'staging')
+
+        out, err, ret = run_spag('env', 'rm', 'staging', env={'SPAG_ENV': 'staging'})
+        self.assertEqual(err, 'Refusing to remove the selected environment "staging". '
+                              'Use --force to remove it anyway.\n')
+        self.assertEqual(ret, 1)
+        self.assertTrue(os.path.exists('.spag/environments/staging.yml'))
+
+        out, err, ret = run_spag('env', 'rm', 'staging', '--force', env={'SPAG_ENV': 'staging'})
+        self.assertEqual((out, err, ret), ('', '', 0))
+        self.assertFalse(os.path.exists('.spag/environments/staging.yml'))
+
+    def test_spag_env_rm_not_found(self):
+        out, err, ret = run_spag('env', 'rm', 'ninnymuggins')
+        self.assertEqual(err, 'Environment not found\n')
+        self.assertEqual(ret, 1)
+
+    def test_spag_env_diff(self):
+        filename = '.spag/environments/other.yml'
+        with open(filename, 'w') as f:
+            f.write(textwrap.dedent("""
+                endpoint: http://localhost:9999
+                password: hunter2
+                secrets: [password]
+                headers:
+                    Accept: application/json
+                """))
+        self.addCleanup(rm_file, filename)
+
+        out, err, ret = run_spag('env', 'diff', 'default', 'other')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.splitlines(), [
+            '- dir: %s' % RESOURCES_DIR,
+            '~ endpoint: %s -> http://localhost:9999' % ENDPOINT,
+            '+ headers.Accept: application/json',
+            '+ password: ********',
+            '+ secrets: ["password"]',
+        ])
+
+        out, err, ret = run_spag('env', 'diff', 'default', 'default')
+        self.assertEqual((out, err, ret), ('', '', 0))
+
+    def test_spag_env_import_dotenv(self):
+        self.addCleanup(rm_file, '.spag/environments/service.yml')
+        out, err, ret = run_spag('env', 'import', '--dotenv',
+                                 os.path.join(DOTENV_DIR, 'service.env'), 'service')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(yaml.load(out), {
+            'endpoint': ENDPOINT,
+            'DATABASE_URL': 'postgres://db:5432/things',
+            'headers': {'X-Service': 'spag'},
+            'GREETING': 'hello world',
+        })
+
+    def test_spag_env_import_bad_dotenv(self):
+        filename = os.path.join(DOTENV_DIR, 'bad.env')
+        out, err, ret = run_spag('env', 'import', '--dotenv', filename)
+        self.assertEqual(err, '%s:2: Expected a line like KEY=value\n' % filename)
+        self.assertEqual(ret, 1)
+
+    def test_spag_env_import_dotenv_key_conflict(self):
+        filename = os.path.join(DOTENV_DIR, 'conflict.env')
+        out, err, ret = run_spag('env', 'import', '--dotenv', filename, 'conflict')
+        self.assertEqual(err, '%s:3: Failed to set "headers.Accept" because "headers" is not '
+                              'a map\n' % filename)
+        self.assertEqual(ret, 1)
+        # nothing was written
+        self.assertFalse(os.path.exists('.spag/environments/conflict.yml'))
+
+    def test_spag_env_export(self):
+        run_spag('env', 'set', 'headers.Accept', 'application/json',
+                 'password', 'hunter2', 'secrets', 'password')
+        run_spag('env', 'set', 'ids', '[1, 2]', '--type', 'yaml')
+
+        warning = ('Warning: the secret "password" was exported as ********. '
+                   'Use --show-secrets to export its value.\n')
+        out, err, ret = run_spag('env', 'export', '--format', 'sh')
+        self.assertEqual(err, warning)
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.splitlines(), [
+            "export dir='%s'" % RESOURCES_DIR,
+            "export endpoint='%s'" % ENDPOINT,
+            "export headers_Accept='application/json'",
+            "export ids='[1,2]'",
+            "export password='********'",
+        ])
+
+        out, err, ret = run_spag('env', 'export', 'default', '--format', 'dotenv')
+        self.assertEqual(err, warning)
+        self.assertEqual(out.splitlines(), [
+            'dir=%s' % RESOURCES_DIR,
+            'endpoint=%s' % ENDPOINT,
+            'headers.Accept=application/json',
+            'ids="[1,2]"',
+            'password="********"',
+        ])
+
+        out, err, ret = run_spag('env', 'export', '--format', 'json')
+        self.assertEqual(err, warning)
+        self.assertEqual(json.loads(out), {
+            'dir': RESOURCES_DIR,
+            'endpoint': ENDPOINT,
+            'headers.Accept': 'application/json',
+            'ids': [1, 2],
+            'password': '********',
+        })
+
+        out, err, ret = run_spag('env', 'export', '--format', 'dotenv', '--show-secrets')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertIn('password=hunter2', out.splitlines())
+
+        out, err, ret = run_spag('env', 'export', '--format', 'xml')
+        self.assertEqual(err, 'Unknown export format "xml". Expected one of sh, dotenv or json\n')
+        self.assertEqual(ret, 1)
+
+    def test_spag_env_flag(self):
+        filename = '.spag/environments/other.yml'
+        with open(filename, 'w') as f:
+            f.write('endpoint: %s\nheaders:\n    mini: other\n' % ENDPOINT)
+        self.addCleanup(rm_file, filename)
+
+        out, err, ret = run_spag('get', '/headers', '--env', 'other')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {'Mini': 'other'})
+
+        out, err, ret = run_spag('--env=other', 'get', '/headers')
+        self.assertEqual(json.loads(out), {'Mini': 'other'})
+
+        out, err, ret = run_spag('get', '/headers', env={'SPAG_ENV': 'other'})
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {'Mini': 'other'})
+
+        # the flag wins over SPAG_ENV
+        out, err, ret = run_spag('get', '/headers', '--env', 'other', env={'SPAG_ENV': 'ninnymuggins'})
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {'Mini': 'other'})
+
+        # the active environment is left alone
+        with open('.spag/environments/active') as f:
+            self.assertEqual(f.read(), 'default')
+        out, err, ret = run_spag('get', '/headers')
+        self.assertEqual(json.loads(out), {})
+
+        out, err, ret = run_spag('env', 'set', 'mini', 'wumbo', '--env', 'other')
+        self.assertEqual(err, '')
+        self.assertIn('"mini": "wumbo"', out)
+        out, err, ret = run_spag('env', 'cat', env={'SPAG_ENV': 'other'})
+        self.assertIn('"mini": "wumbo"', out)
+
+        out, err, ret = run_spag('get', '/headers', '--env', 'ninnymuggins')
+        self.assertEqual(err, 'Environment not found\n')
+        self.assertEqual(ret, 1)
+
+    def test_spag_env_flag_in_templates(self):
+        filename = '.spag/environments/other.yml'
+        with open(filename, 'w') as f:
+            f.write('endpoint: %s\nthing_id: abc\n' % ENDPOINT)
+        self.addCleanup(rm_file, filename)
+
+        out, err, ret = run_spag('get', '/things/{{ [].thing_id }}', '--env', 'other', '--dry-run')
+        self.assertEqual(err, '')
+        self.assertIn('GET %s/things/abc' % ENDPOINT, out)
+        self.assertEqual(ret, 0)
+
+    def test_spag_env_flag_as_an_option_value(self):
+        filename = '.spag/environments/other.yml'
+        with open(filename, 'w') as f:
+            f.write('endpoint: %s/other\n' % ENDPOINT)
+        self.addCleanup(rm_file, filename)
+
+        # "--env" given as the data is sent as the body, and not taken as the flag
+        out, err, ret = run_spag('post', '/things', '--data', '--env', '--env', 'other', '--dry-run')
+        self.assertEqual(err, '')
+        self.assertIn('POST %s/other/things' % ENDPOINT, out)
+        self.assertTrue(out.endswith('Body:\n--env\n'))
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('post', '/things', '-d', '--env', '--dry-run')
+        self.assertEqual(err, '')
+        self.assertIn('POST %s/things' % ENDPOINT, out)
+        self.assertTrue(out.endswith('Body:\n--env\n'))
+
+class TestSpagDir(BaseTest):
+
+    SUBDIR = os.path.join(os.path.dirname(__file__), 'spag_dir_test')
+
+    def setUp(self):
+        super(TestSpagDir, self).setUp()
+        rm_dir(self.SUBDIR)
+        os.makedirs(self.SUBDIR)
+
+    def tearDown(self):
+        rm_dir(self.SUBDIR)
+        super(TestSpagDir, self).tearDown()
+
+    def test_spag_dir_found_in_parent(self):
+        out, err, ret = run_spag('env', 'set', 'mini', 'wumbo')
+        self.assertEqual((err, ret), ('', 0))
+
+        out, err, ret = run_spag('env', 'cat', cwd=self.SUBDIR)
+        self.assertEqual(err, '')
+        self.assertIn('"mini": "wumbo"', out)
+        self.assertEqual(ret, 0)
+        self.assertFalse(os.path.exists(os.path.join(self.SUBDIR, '.spag')))
+
+    def test_relative_dir_found_from_subdirectory(self):
+        run_spag('env', 'set', 'endpoint', ENDPOINT)
+        run_spag('env', 'set', 'dir', os.path.relpath(TEMPLATES_DIR))
+
+        out, err, ret = run_spag('request', 'get_default', '--dry-run', cwd=self.SUBDIR)
+        self.assertEqual(err, '')
+        self.assertIn('GET {0}/things/mydefaultid\n'.format(ENDPOINT), out)
+        self.assertEqual(ret, 0)
+
+    def test_spag_dir_env_var(self):
+        spag_dir = os.path.join(self.SUBDIR, 'other')
+        out, err, ret = run_spag('env', 'set', 'mini', 'wumbo', env={'SPAG_DIR': spag_dir})
+        self.assertEqual((err, ret), ('', 0))
+        self.assertTrue(os.path.exists(os.path.join(spag_dir, 'environments', 'default.yml')))
+
+        # the project's environment is untouched
+        out, err, ret = run_spag('env', 'cat')
+        self.assertEqual(out, '---\n{}\n')
+
+    def test_spag_dir_flag(self):
+        spag_dir = os.path.join(self.SUBDIR, 'other')
+        out, err, ret = run_spag('--spag-dir', spag_dir, 'env', 'set', 'mini', 'wumbo')
+        self.assertEqual((err, ret), ('', 0))
+
+        out, err, ret = run_spag('env', 'cat', '--spag-dir=%s' % spag_dir)
+        self.assertEqual(err, '')
+        self.assertIn('"mini": "wumbo"', out)
+        self.assertEqual(ret, 0)
+
+    def test_spag_init(self):
+        out, err, ret = run_spag('init', cwd=self.SUBDIR)
+        self.assertEqual(err, '')
+        self.assertEqual(out, 'Created spag directory .spag\n')
+        self.assertEqual(ret, 0)
+        self.assertTrue(os.path.exists(
+            os.path.join(self.SUBDIR, '.spag', 'environments', 'default.yml')))
+
+        # the new directory is used from here on, instead of the one above
+        out, err, ret = run_spag('env', 'set', 'mini', 'wumbo', cwd=self.SUBDIR)
+        self.assertEqual((err, ret), ('', 0))
+        out, err, ret = run_spag('env', 'cat')
+        self.assertEqual(out, '---\n{}\n')
+
+        out, err, ret = run_spag('init', cwd=self.SUBDIR)
+        self.assertEqual(err, 'A spag directory already exists at .spag\n')
+        self.assertEqual(ret, 1)
+
+
+class TestSpagSecrets(BaseTest):
+
+    def setUp(self):
+        super(TestSpagSecrets, self).setUp()
+        assert run_spag('env', 'set', 'endpoint', '%s' % ENDPOINT)[2] == 0
+
+    def test_secrets_are_redacted(self):
+        out, err, ret = run_spag('env', 'set', 'headers.X-Auth-Token', 's3cr3t',
+                                 'password', 'hunter2',
+                                 'secrets', 'headers.X-Auth-Token, password')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(yaml.load(out)['headers']['X-Auth-Token'], '********')
+        self.assertEqual(yaml.load(out)['password'], '********')
+        self.assertNotIn('s3cr3t', out)
+
+        # the real values are still sent
+        out, err, ret = run_spag('get', '/params/all?password=@[].password',
+                                 '-H', 'X-Auth-Token: @[].headers.X-Auth-Token')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {'password': ['hunter2']})
+
+        out, err, ret = run_spag('history')
+        self.assertEqual(out, '0: GET %s/params/all?password=********\n' % ENDPOINT)
+        out, err, ret = run_spag('history', '0')
+        self.assertIn('X-Auth-Token: ********\n', out)
+
+        for filename in (SPAG_HISTORY_FILE, os.path.join(SPAG_REMEMBERS_DIR, 'last.yml')):
+            with open(filename) as f:
+                contents = f.read()
+            self.assertNotIn('s3cr3t', contents)
+            self.assertNotIn('hunter2', contents)
+
+    def test_secrets_from_every_environment_are_redacted(self):
+        filename = '.spag/environments/parent.yml'
+        with open(filename, 'w') as f:
+            f.write('endpoint: %s\npassword: hunter2\nsecrets: [password]\n' % ENDPOINT)
+        self.addCleanup(rm_file, filename)
+        filename = '.spag/environments/child.yml'
+        with open(filename, 'w') as f:
+            f.write('extends: parent\nheaders:\n    X-Auth-Token: s3cr3t\n'
+                    'secrets: [headers.X-Auth-Token]\n')
+        self.addCleanup(rm_file, filename)
+        # a global environment's secrets are kept under the project's too
+        os.makedirs(GLOBAL_ENV_DIR)
+        with open(os.path.join(GLOBAL_ENV_DIR, 'child.yml'), 'w') as f:
+            f.write('pin: 8675309\nsecrets: [pin]\n')
+
+        out, err, ret = run_spag('get', '/params/all?password={{ [].password }}&pin={{ [].pin }}',
+                                 '-H', 'X-Auth-Token: @[].headers.X-Auth-Token', '--env', 'child')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {'password': ['hunter2'], 'pin': ['8675309']})
+
+        out, err, ret = run_spag('history', '0')
+        self.assertIn('X-Auth-Token: ********\n', out)
+        self.assertIn('password=********', out)
+        self.assertIn('pin=********', out)
+        with open(SPAG_HISTORY_FILE) as f:
+            contents = f.read()
+        for secret in ('hunter2', 's3cr3t', '8675309'):
+            self.assertNotIn(secret, contents)
+
+    def test_secret_header_from_arguments(self):
+        run_spag('env', 'set', 'secrets', 'headers.Authorization')
+        out, err, ret = run_spag('get', '/headers', '-v', '-H', 'Authorization: Basic abc')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertIn('Authorization: ********\n', out)
+        self.assertNotIn('Basic abc', out.split('Response')[0])
+
+    def test_nothing_saved_without_secrets(self):
+        filename = '.spag/environments/orphan.yml'
+        with open(filename, 'w') as f:
+            f.write('extends: ninnymuggins\nsecrets: [password]\n')
+        self.addCleanup(rm_file, filename)
+        run_spag('env', 'activate', 'orphan')
+        self.addCleanup(run_spag, 'env', 'deactivate')
+
+        out, err, ret = run_spag('post', '/things', '-e', ENDPOINT,
+                                 '-H', 'Content-Type: application/json', '--data', '{"id": "wumbo"}')
+        self.assertEqual(err,
+            'Failed to load the secrets in the environment -- Failed to load environment '
+            '"ninnymuggins" extended by .spag/environments/orphan.yml -- Environment not found\n')
+        self.assertEqual(ret, 1)
+        self.assertFalse(os.path.exists(SPAG_HISTORY_FILE))
+        self.assertFalse(os.path.exists(SPAG_REMEMBERS_DIR))
+
+        # the request wasn't sent either
+        out, err, ret = run_spag('get', '/things', '--env', 'default')
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {'things': []})
+
+    def test_history_as_curl_warns_about_secrets(self):
+        run_spag('env', 'set', 'headers.X-Auth-Token', 's3cr3t',
+                 'secrets', 'headers.X-Auth-Token')
+        _, err, ret = run_spag('get', '/headers')
+        self.assertEqual((err, ret), ('', 0))
+
+        out, err, ret = run_spag('history', '0', '--as-curl')
+        self.assertEqual(ret, 0)
+        self.assertIn("-H 'X-Auth-Token: ********'", out)
+        self.assertEqual(err, 'Warning: this request contains redacted secrets (********). '
+                              'Fill them in before running the command.\n')
+
 
 class TestSpagRemembers(BaseTest):
 
@@ -811,6 +1507,36 @@ class TestSpagTemplate(BaseTest):
         self.assertEqual(out.strip()[:len(prefix)], prefix)
         self.assertEqual(out.strip()[-len(suffix):], suffix)
 
+    def test_dry_run(self):
+        self._post_thing('wumbo')
+        out, err, ret = run_spag('env', 'set', 'headers.mini', 'wumbo')
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('request', 'post_thing', '--with', 'thing_id', '@id',
+                                 '-q', 'a=b', '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out, textwrap.dedent("""\
+            POST {0}/things?a=b
+            Accept: application/json
+            Content-Type: application/json
+            mini: wumbo
+            Body:
+            {{
+                "id": "wumbo"
+            }}
+
+            """).format(ENDPOINT))
+
+        out, err, ret = run_spag('get', '/things/@id', '-H', 'a: b', '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out, 'GET %s/things/wumbo\na: b\nmini: wumbo\nBody:\n\n' % ENDPOINT)
+
+        # only the original post is in the history
+        out, err, ret = run_spag('history')
+        self.assertEqual(out, '0: POST %s/things\n' % ENDPOINT)
+
     def test_spag_template_w_remember_as_flag(self):
         out, err, ret = run_spag('request', 'post_thing', '-v',
                                  '--with', 'thing_id', 'wumbo',
@@ -823,9 +1549,15 @@ class TestSpagTemplate(BaseTest):
         self.assertEqual(ret, 0)
         self.assertEquals(json.loads(out), {"id": "wumbo"})
 
+    def _assert_template_error(self, err, source, text, column, message):
+        expected = '{0}:1:{1}: {2}\n{3}\n{4}^'.format(
+            source, column, message, text, ' ' * (column - 1))
+        self.assertEqual(err.rstrip('\n'), expected)
+
     def test_error_using_shortcut_syntax_inside_template_list(self):
         out, err, ret = run_spag('get', '/things/{{a, @id}}')
-        self.assertEqual(err.strip(), "Invalid character '@' found in template item")
+        self._assert_template_error(err, 'path', '/things/{{a, @id}}', 14,
+            "Invalid character '@' found in template item")
         self.assertEqual(ret, 1)
         self.assertEqual(out, '')
 
@@ -841,42 +1573,73 @@ class TestSpagTemplate(BaseTest):
 
     def test_error_message_on_empty_list(self):
         _, err, _ = run_spag('get', '/things/{{}}')
-        self.assertEqual(err.strip(),
+        self._assert_template_error(err, 'path', '/things/{{}}', 11,
             "Expected a template list item, but found the end of the list '}}'")
 
     def test_error_message_on_unclosed_list(self):
-        eof_msg = "Expected a template list item, but found eof"
         _, err, _ = run_spag('get', '/things/{{')
-        self.assertEqual(err.strip(), eof_msg)
+        self._assert_template_error(err, 'path', '/things/{{', 9,
+            "Expected a template list item, but found eof")
 
         _, err, _ = run_spag('get', '/things/@')
-        self.assertEqual(err.strip(), eof_msg)
+        self._assert_template_error(err, 'path', '/things/@', 9,
+            "Expected a template list item, but found eof")
 
     def test_error_message_on_missing_list_item(self):
         _, err, _ = run_spag('get', '/things/@:')
-        self.assertEqual(err.strip(), "Expected a template list item, but found ':'")
+        self._assert_template_error(err, 'path', '/things/@:', 10,
+            "Expected a template list item, but found ':'")
 
         _, err, _ = run_spag('get', '/things/@ :')
-        self.assertEqual(err.strip(), "Expected a template list item, but found ':'")
+        self._assert_template_error(err, 'path', '/things/@ :', 11,
+            "Expected a template list item, but found ':'")
 
         _, err, _ = run_spag('get', '/things/{{ : ')
-        self.assertEqual(err.strip(), "Expected a template list item, but found ':'")
+        self._assert_template_error(err, 'path', '/things/{{ : ', 12,
+            "Expected a template list item, but found ':'")
 
         _, err, _ = run_spag('get', '/things/@,')
-        self.assertEqual(err.strip(), "Expected a template list item, but found ','")
+        self._assert_template_error(err, 'path', '/things/@,', 10,
+            "Expected a template list item, but found ','")
 
         _, err, _ = run_spag('get', '/things/@ ,')
-        self.assertEqual(err.strip(), "Expected a template list item, but found ','")
+        self._assert_template_error(err, 'path', '/things/@ ,', 11,
+            "Expected a template list item, but found ','")
 
         _, err, _ = run_spag('get', '/things/{{ : ')
-        self.assertEqual(err.strip(), "Expected a template list item, but found ':'")
+        self._assert_template_error(err, 'path', '/things/{{ : ', 12,
+            "Expected a template list item, but found ':'")
 
     def test_error_message_on_invalid_list_item(self):
         _, err, _ = run_spag('get', '/things/{{/}} ')
-        self.assertEqual(err.strip(), "Invalid character '/' found in template item")
+        self._assert_template_error(err, 'path', '/things/{{/}} ', 11,
+            "Invalid character '/' found in template item")
 
         _, err, _ = run_spag('get', '/things/@/')
-        self.assertEqual(err.strip(), "Invalid character '/' found in template item")
+        self._assert_template_error(err, 'path', '/things/@/', 10,
+            "Invalid character '/' found in template item")
+
+    def test_error_location_in_with_and_env_set_values(self):
+        _, err, ret = run_spag('request', 'post_thing', '--with', 'thing_id', '{{ a, }}')
+        self._assert_template_error(err, 'thing_id', '{{ a, }}', 7,
+            "Expected a template list item, but found the end of the list '}}'")
+        self.assertEqual(ret, 1)
+
+        _, err, ret = run_spag('env', 'set', 'thing_id', '{{ a, }}')
+        self._assert_template_error(err, 'thing_id', '{{ a, }}', 7,
+            "Expected a template list item, but found the end of the list '}}'")
+        self.assertEqual(ret, 1)
+
+    def test_error_message_in_request_file(self):
+        out, err, ret = run_spag('request', 'templates/bad_template',
+                                 '--with', 'thing_id', 'wumbo')
+        lines = err.splitlines()
+        self.assertTrue(lines[0].endswith("bad_template.yml:4:26: Expected a "
+            "template list item, but found the end of the list '}}'"))
+        self.assertEqual(lines[1:], ['    {"id": "{{ thing_id, }}"}',
+                                     ' ' * 25 + '^'])
+        self.assertEqual(out, '')
+        self.assertEqual(ret, 1)
 
     def test_inspect(self):
         out, err, ret = run_spag('request', 'inspect', 'show_params_test')
@@ -894,6 +1657,470 @@ class TestSpagTemplate(BaseTest):
                 * key ["response", "body", "id"] from the request saved as "other"
             """).strip())
 
+    def test_process_env_var(self):
+        data = '{"id": "{{ thing_id, $SPAG_THING_ID }}"}'
+        out, err, ret = run_spag('post', '/things', '--data', data,
+                                 '-H', 'Content-type: application/json',
+                                 env={'SPAG_THING_ID': 'wumbo'})
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {"id": "wumbo"})
+        self.assertEqual(ret, 0)
+
+        # the with key comes first in the list
+        out, err, ret = run_spag('post', '/things', '--data', data,
+                                 '-H', 'Content-type: application/json',
+                                 '--with', 'thing_id', 'mini',
+                                 env={'SPAG_THING_ID': 'wumbo'})
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {"id": "mini"})
+        self.assertEqual(ret, 0)
+
+    def test_process_env_var_shortcut(self):
+        self._post_thing('wumbo')
+        out, err, ret = run_spag('get', '/things/@$SPAG_THING_ID',
+                                 env={'SPAG_THING_ID': 'wumbo'})
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {"id": "wumbo"})
+        self.assertEqual(ret, 0)
+
+    def test_process_env_var_not_set(self):
+        out, err, ret = run_spag('get', '/things/{{ $SPAG_UNSET_THING_ID }}')
+        self.assertEqual(err, 'Failed to substitute for {{ $SPAG_UNSET_THING_ID }}\n')
+        self.assertEqual(out, '')
+        self.assertEqual(ret, 1)
+
+    def test_filters(self):
+        self._post_thing('wumbo')
+        out, err, ret = run_spag('get', '/things/{{ thing_id | trim | lower }}',
+                                 '--with', 'thing_id', '  WUMBO ')
+        self.assertEqual(err, '')
+        self.assertEqual(json.loads(out), {"id": "wumbo"})
+        self.assertEqual(ret, 0)
+
+    def test_filters_with_default(self):
+        out, err, ret = run_spag('get', '/things/{{ thing_id : mini | urlencode }}',
+                                 '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.splitlines()[0], 'GET {0}/things/mini'.format(ENDPOINT))
+
+    def test_unknown_filter(self):
+        out, err, ret = run_spag('get', '/things/{{ thing_id | wumbo }}',
+                                 '--with', 'thing_id', 'a')
+        self.assertTrue(err.startswith('path:1:23: Unknown template filter "wumbo"'))
+        self.assertEqual(out, '')
+        self.assertEqual(ret, 1)
+
+    def test_functions(self):
+        out, err, ret = run_spag('post', '/things', '--data',
+                                 '{"id": "{{ random_string(12) }}"}',
+                                 '-H', 'Content-type: application/json')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        thing_id = json.loads(out)['id']
+        self.assertEqual(len(thing_id), 12)
+
+        # the generated value is saved in history, so the request can be repeated
+        out, err, ret = run_spag('history', '0')
+        self.assertEqual(err, '')
+        self.assertIn(thing_id, out)
+        self.assertEqual(ret, 0)
+
+    def test_unknown_function(self):
+        out, err, ret = run_spag('get', '/things/{{ wumbo() }}')
+        self.assertTrue(err.startswith('path:1:12: Unknown template function wumbo()'))
+        self.assertEqual(out, '')
+        self.assertEqual(ret, 1)
+
+    def _list_things(self):
+        self._post_thing('wumbo')
+        out, err, ret = run_spag('get', '/things')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+
+    def test_json_in_body(self):
+        self._list_things()
+        out, err, ret = run_spag('post', '/things', '--data', '{"things": @things}',
+                                 '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.strip().splitlines()[-1], '{"things": [{"id":"wumbo"}]}')
+
+    def test_json_in_request_file_body(self):
+        self._list_things()
+        out, err, ret = run_spag('request', 'post_things_json', '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.strip().splitlines()[0], 'POST {0}/things/wumbo'.format(ENDPOINT))
+        self.assertEqual(out.strip().splitlines()[-1], '{"things": [{"id":"wumbo"}]}')
+
+    def test_json_refused_in_url(self):
+        self._list_things()
+        out, err, ret = run_spag('get', '/things/@things', '--dry-run')
+        self.assertEqual(err, 'Failed to substitute for {{ last.response.body.things }}\n')
+        self.assertEqual(ret, 1)
+
+    def test_json_filter(self):
+        self._list_things()
+        out, err, ret = run_spag('get', '/things?q={{ last.response.body.things | json }}',
+                                 '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.splitlines()[0],
+                         'GET {0}/things?q=[{{"id":"wumbo"}}]'.format(ENDPOINT))
+
+        # a string comes out as a json string
+        out, err, ret = run_spag('post', '/things', '--dry-run',
+                                 '--data', '{"id": {{ last.response.body.things.0.id | json }}}')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.strip().splitlines()[-1], '{"id": "wumbo"}')
+
+    def test_typed_env_values(self):
+        out, err, ret = run_spag('env', 'set', 'limit', '10', '--type', 'int')
+        self.assertEqual(err, '')
+        self.assertIn('"limit": 10\n', out)
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('env', 'set', 'tags', '[a, b]', 'meta', '{x: 1}', '--type', 'yaml')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('post', '/things?limit=@[].limit', '--dry-run', '--data',
+                                 '{"tags": @[].tags, "meta": {{ [].meta }}, "n": @[].limit}')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.splitlines()[0], 'POST {0}/things?limit=10'.format(ENDPOINT))
+        self.assertEqual(out.strip().splitlines()[-1],
+                         '{"tags": ["a","b"], "meta": {"x":1}, "n": 10}')
+
+        # lists and maps aren't substituted outside of a body
+        out, err, ret = run_spag('get', '/things/@[].tags', '--dry-run')
+        self.assertEqual(err, 'Failed to substitute for {{ [].tags }}\n')
+        self.assertEqual(ret, 1)
+
+    def test_hand_written_typed_env_values(self):
+        with open('.spag/environments/default.yml', 'w') as f:
+            f.write('endpoint: %s\nlimit: 5\nverbose: true\n' % ENDPOINT)
+        out, err, ret = run_spag('get', '/things?limit={{ [].limit }}&v=@[].verbose',
+                                 '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(out.splitlines()[0], 'GET {0}/things?limit=5&v=true'.format(ENDPOINT))
+        self.assertEqual(ret, 0)
+
+    def test_invalid_typed_env_value(self):
+        out, err, ret = run_spag('env', 'set', 'limit', 'ten', '--type', 'int')
+        self.assertEqual(err, 'Invalid int value "ten"\n')
+        self.assertEqual(ret, 1)
+
+    def test_key_path_selectors(self):
+        self._post_thing('mini')
+        self._list_things()
+        out, err, ret = run_spag('get', '/things/{{ last.response.body.things[?id=="wumbo"].id }}',
+                                 '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.splitlines()[0], 'GET {0}/things/wumbo'.format(ENDPOINT))
+
+        out, err, ret = run_spag('get', '/things/@body.things.length', '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.splitlines()[0], 'GET {0}/things/2'.format(ENDPOINT))
+
+        out, err, ret = run_spag('post', '/things', '--data', '{"ids": @body.things.*.id}',
+                                 '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        ids = json.loads(out.strip().splitlines()[-1])['ids']
+        self.assertEqual(sorted(ids), ['mini', 'wumbo'])
+
+    def test_env_key_path_selectors(self):
+        out, err, ret = run_spag('env', 'set', 'hosts',
+                                 '[{name: a, port: 1}, {name: b, port: 2}, {name: c}]',
+                                 '--type', 'yaml')
+        self.assertEqual((err, ret), ('', 0))
+        out, err, ret = run_spag('post', '/things/{{ [].hosts.-1.name }}', '--dry-run', '--data',
+                                 '{"ports": @[].hosts.*.port, "b": {{ [].hosts[?name=="b"].port }}}')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.splitlines()[0], 'POST {0}/things/c'.format(ENDPOINT))
+        self.assertEqual(out.strip().splitlines()[-1], '{"ports": [1,2], "b": 2}')
+
+    def test_escapes(self):
+        data = r'{"to": "ops@@example.com", "msg": "hi \{{ name }}"}'
+        out, err, ret = run_spag('post', '/things', '--data', data, '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out.strip().splitlines()[-1],
+                         '{"to": "ops@example.com", "msg": "hi {{ name }}"}')
+
+    def test_no_template(self):
+        data = '{"to": "ops@example.com", "msg": "hi {{ name }}"}'
+        out, err, ret = run_spag('post', '/things/@id', '--data', data,
+                                 '-H', 'X-Thing: {{ a }}', '-q', 'email=a@b.c',
+                                 '--no-template', '--dry-run')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out, textwrap.dedent("""\
+            POST {0}/things/@id?email=a%40b.c
+            X-Thing: {{{{ a }}}}
+            Body:
+            {1}
+            """).format(ENDPOINT, data))
+
+    def test_prompt(self):
+        out, err, ret = run_spag('request', 'post_thing', '--prompt',
+                                 input='wumbo\nn\n')
+        self.assertEqual(err, textwrap.dedent("""\
+            {{ thing_id }} needs one of
+                * flag "--with thing_id <value>"
+            thing_id: Save these answers as defaults in the active environment? [y/N]: """))
+        self.assertEqual(json.loads(out), {"id": "wumbo"})
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('env', 'cat')
+        self.assertNotIn('thing_id', out)
+
+    def test_prompt_save_answers(self):
+        out, err, ret = run_spag('request', 'post_thing', '--prompt',
+                                 input='wumbo\ny\n')
+        self.assertEqual(json.loads(out), {"id": "wumbo"})
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('env', 'cat')
+        self.assertIn('"thing_id": "wumbo"', out)
+
+        # the saved answer is the default next time
+        out, err, ret = run_spag('request', 'post_thing', '--prompt',
+                                 input='\nn\n')
+        self.assertIn('thing_id [wumbo]: ', err)
+        self.assertEqual(json.loads(out), {"id": "wumbo"})
+        self.assertEqual(ret, 0)
+
+    def test_prompt_secret_default_is_hidden(self):
+        run_spag('env', 'set', 'thing_id', 'wumbo', 'secrets', 'thing_id')
+        out, err, ret = run_spag('request', 'post_thing', '--prompt',
+                                 input='\nn\n')
+        self.assertIn('thing_id [********]: ', err)
+        self.assertEqual(json.loads(out), {"id": "wumbo"})
+        self.assertEqual(ret, 0)
+
+    def test_no_prompt_without_terminal(self):
+        # stdin isn't a terminal here, so spag doesn't ask unless told to
+        out, err, ret = run_spag('request', 'post_thing', input='wumbo\nn\n')
+        self.assertEqual(err, 'Failed to substitute for {{ thing_id }}\n')
+        self.assertEqual(ret, 1)
+
+    def test_no_prompt(self):
+        out, err, ret = run_spag('request', 'post_thing', '--prompt', '--no-prompt',
+                                 input='wumbo\nn\n')
+        self.assertEqual(err, 'Failed to substitute for {{ thing_id }}\n')
+        self.assertEqual(ret, 1)
+
+    def test_saved_answers_are_only_defaults(self):
+        run_spag('env', 'set', 'thing_id', 'wumbo')
+        out, err, ret = run_spag('request', 'post_thing')
+        self.assertEqual(err, 'Failed to substitute for {{ thing_id }}\n')
+        self.assertEqual(ret, 1)
+
+class TestSpagExpect(BaseTest):
+
+    def setUp(self):
+        super(TestSpagExpect, self).setUp()
+        assert run_spag('env', 'set', 'endpoint', '%s' % ENDPOINT)[2] == 0
+        assert run_spag('env', 'set', 'dir', '%s' % TEMPLATES_DIR)[2] == 0
+
+    def test_expectations_pass(self):
+        out, err, ret = run_spag('request', 'expect_auth')
+        self.assertEqual(json.loads(out), {"token": "abcde"})
+        self.assertEqual(err.strip(),
+            textwrap.dedent("""
+            Expectations: 6 passed, 0 failed
+                PASS status is 2xx
+                PASS header "Content-Length" is present
+                PASS header "Content-Type" is "application/json"
+                PASS body token is "abcde"
+                PASS body token matches "^ab"
+                PASS body contains "abc"
+            """).strip())
+        self.assertEqual(ret, 0)
+
+    def test_expectations_fail(self):
+        out, err, ret = run_spag('request', 'expect_auth',
+                                 '--with', 'status', '201',
+                                 '--with', 'token', 'wumbo')
+        self.assertEqual(json.loads(out), {"token": "abcde"})
+        self.assertIn('Expectations: 4 passed, 2 failed\n', err)
+        self.assertIn('FAIL status is 201 (got 200)\n', err)
+        self.assertIn('FAIL body token is "wumbo" (got "abcde")\n', err)
+        self.assertTrue(err.endswith('2 of 6 expectations failed\n'))
+        self.assertEqual(ret, 1)
+
+    def test_failed_expectations_are_still_remembered(self):
+        _, _, ret = run_spag('request', 'expect_auth', '--with', 'status', '404')
+        self.assertEqual(ret, 1)
+        out, err, ret = run_spag('history')
+        self.assertEqual(out, '0: GET %s/auth\n' % ENDPOINT)
+
+
+class TestSpagExtract(BaseTest):
+
+    def setUp(self):
+        super(TestSpagExtract, self).setUp()
+        assert run_spag('env', 'set', 'endpoint', '%s' % ENDPOINT)[2] == 0
+        assert run_spag('env', 'set', 'dir', '%s' % TEMPLATES_DIR)[2] == 0
+
+    def test_extract_from_request_file(self):
+        out, err, ret = run_spag('request', 'extract_auth')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {"token": "abcde"})
+
+        out, err, ret = run_spag('get', '/headers')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {"X-Auth-Token": "abcde"})
+
+    def test_set_env_flag(self):
+        out, err, ret = run_spag('get', '/auth', '--set-env', 'token=body.token',
+                                 '--set-env', 'auth.length=headers.Content-Length')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('env', 'cat')
+        self.assertEqual(yaml.load(out)['token'], 'abcde')
+        self.assertEqual(yaml.load(out)['auth']['length'], '22')
+
+    def test_set_env_flag_missing_value(self):
+        out, err, ret = run_spag('get', '/auth', '--set-env', 'token=body.nope')
+        self.assertEqual(err, "Failed to extract body.nope from the response -- Invalid key 'nope'\n")
+        self.assertEqual(ret, 1)
+
+    def test_set_env_flag_invalid_source(self):
+        out, err, ret = run_spag('get', '/auth', '--set-env', 'token=token')
+        self.assertEqual(out, '')
+        self.assertIn('Invalid response value "token"', err)
+        self.assertEqual(ret, 1)
+
+    def test_set_env_flag_skipped_on_failure(self):
+        out, err, ret = run_spag('get', '/things/nope', '--set-env', 'status=status')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('env', 'cat')
+        self.assertNotIn('status', yaml.load(out))
+
+
+class TestSpagRun(BaseTest):
+
+    def setUp(self):
+        super(TestSpagRun, self).setUp()
+        assert run_spag('env', 'set', 'endpoint', '%s' % ENDPOINT)[2] == 0
+        assert run_spag('env', 'set', 'dir', '%s' % TEMPLATES_DIR)[2] == 0
+
+    def test_run_suite(self):
+        out, err, ret = run_spag('run', os.path.join(SUITES_DIR, 'create_thing.yml'))
+        self.assertEqual(err, '')
+        self.assertEqual(out,
+            "1. post_thing: POST {0}/things -> 201 ok\n"
+            "2. get_default: GET {0}/things/wumbo -> 200 ok\n"
+            "Ran 2 of 2 steps: 2 passed, 0 failed\n"
+            .format(ENDPOINT))
+        self.assertEqual(ret, 0)
+        self.assertTrue(os.path.exists(os.path.join(SPAG_REMEMBERS_DIR, 'created.yml')))
+
+    def test_run_suite_stops_on_failure(self):
+        out, err, ret = run_spag('run', os.path.join(SUITES_DIR, 'missing_thing.yml'))
+        self.assertEqual(out,
+            "1. get_default: GET {0}/things/nope -> 404 FAILED\n"
+            "Ran 1 of 3 steps: 0 passed, 1 failed\n"
+            .format(ENDPOINT))
+        self.assertEqual(ret, 1)
+
+    def test_run_suite_continue_on_error(self):
+        out, err, ret = run_spag('run', os.path.join(SUITES_DIR, 'missing_thing.yml'),
+                                 '--continue-on-error')
+        self.assertEqual(out,
+            "1. get_default: GET {0}/things/nope -> 404 FAILED\n"
+            "2. get_default: GET {0}/things/mydefaultid -> 404 FAILED\n"
+            "3. post_thing: POST {0}/things -> 201 ok\n"
+            "Ran 3 of 3 steps: 1 passed, 2 failed\n"
+            .format(ENDPOINT))
+        self.assertEqual(ret, 1)
+
+
+class TestSpagImport(BaseTest):
+
+    IMPORT_DIR = os.path.join(os.path.dirname(__file__), 'imported')
+
+    def setUp(self):
+        super(TestSpagImport, self).setUp()
+        assert run_spag('env', 'set', 'endpoint', '%s' % ENDPOINT)[2] == 0
+        assert run_spag('env', 'set', 'dir', '%s' % self.IMPORT_DIR)[2] == 0
+        self.addCleanup(rm_dir, self.IMPORT_DIR)
+
+    def test_import_curl(self):
+        command = ("curl '%s/things?a=b%%20c' -H 'Content-Type: application/json' "
+                   "--data-raw '{\"id\": \"wumbo\"}'" % ENDPOINT)
+        out, err, ret = run_spag('import', 'curl', command, '--save', 'v2/post_wumbo')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        filename = os.path.join(self.IMPORT_DIR, 'v2', 'post_wumbo.yml')
+        self.assertEqual(out, 'Saved request file %s\n' % filename)
+        self.assertEqual(yaml.load(open(filename)), {
+            'method': 'POST',
+            'uri': '/things',
+            'headers': {'Content-Type': 'application/json'},
+            'params': {'a': 'b c'},
+            'body': '{"id": "wumbo"}',
+        })
+
+        out, err, ret = run_spag('request', 'post_wumbo')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {"id": "wumbo"})
+
+    def test_import_curl_data_from_file(self):
+        # templates in the data are escaped, so they're sent as is
+        os.makedirs(self.IMPORT_DIR)
+        data_file = os.path.join(self.IMPORT_DIR, 'thing.json')
+        with open(data_file, 'w') as f:
+            f.write('{\n"id": "{{ wumbo }}@example.com"\n}\n')
+        command = ("curl -sSL %s/things -H 'Content-Type: application/json' -d @%s"
+                   % (ENDPOINT, data_file))
+        out, err, ret = run_spag('import', 'curl', command, '--save', 'post_wumbo')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+
+        out, err, ret = run_spag('request', 'post_wumbo')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(json.loads(out), {"id": "{{ wumbo }}@example.com"})
+
+    def test_import_curl_data_from_missing_file(self):
+        out, err, ret = run_spag('import', 'curl', 'curl %s/things -d @nope.json' % ENDPOINT,
+                                 '--save', 'post_wumbo')
+        self.assertEqual(err, 'Failed to read data for -d @nope.json: '
+                              'File nope.json does not exist\n')
+        self.assertEqual(ret, 1)
+
+    def test_import_curl_suggests_endpoint(self):
+        out, err, ret = run_spag('import', 'curl', 'curl http://example.com/things', '-s', 'things')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertIn('    spag env set endpoint http://example.com\n', out)
+
+    def test_import_curl_wont_overwrite(self):
+        _, _, ret = run_spag('import', 'curl', 'curl %s/auth' % ENDPOINT, '-s', 'auth')
+        self.assertEqual(ret, 0)
+        _, err, ret = run_spag('import', 'curl', 'curl %s/auth' % ENDPOINT, '-s', 'auth')
+        self.assertIn('already exists. Use --force to overwrite it.', err)
+        self.assertEqual(ret, 1)
+        _, err, ret = run_spag('import', 'curl', 'curl %s/auth' % ENDPOINT, '-s', 'auth', '--force')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+
+
 class TestSpagHistory(BaseTest):
 
     def setUp(self):
@@ -953,6 +2180,22 @@ class TestSpagHistory(BaseTest):
         self.assertIn('GET %s/things' % ENDPOINT, out)
         self.assertEqual(ret, 0)
 
+    def test_spag_history_show_index_as_curl(self):
+        _, err, _ = run_spag('post', '/things', '-d', '{"id": "it\'s"}',
+                             '-H', 'Content-type: application/json', '-q', 'a=b')
+        self.assertEqual(err, '')
+
+        out, err, ret = run_spag('history', '0', '--as-curl')
+        self.assertEqual(err, '')
+        self.assertEqual(ret, 0)
+        self.assertEqual(out, textwrap.dedent("""\
+            curl -X POST '{0}/things?a=b' \\
+              -H 'Content-Type: application/json' \\
+              --data-raw '{{
+              "id": "it'\\''s"
+            }}'
+            """).format(ENDPOINT))
+
     def test_spag_history_show_index_invalid_id(self):
         # Make a request
         _, err, _ = run_spag('get', '/things')
@@ -55,6 +55,13 @@ def delete_thing(id):
     return ('', 204)
 
 
+@app.route('/things/<id>', methods=['PURGE'])
+def purge_thing(id):
+    return jsonify({"method": request.method, "id": id,
+                    "key": request.headers.get('X-Purge-Key'),
+                    "data": request.get_data(as_text=True)})
+
+
 @app.route('/clear', methods=['GET', 'POST', 'DELETE'])
 def clear():
     database.clear()
@@ -81,5 +88,9 @@ def raw_json():
 def params():
     return jsonify({key: value for key, value in request.args.items()})
 
+@app.route('/params/all', methods=['GET'])
+def all_params():
+    return jsonify({key: request.args.getlist(key) for key in request.args.keys()})
+
 if __name__ == '__main__':
     app.run(debug=True)